@@ -0,0 +1,98 @@
+use crate::SixelMode;
+use crate::errors::Result;
+use crate::layout::{SizeOptions, scale_to_terminal};
+use crate::terminal::get_terminal_cells;
+use image::{DynamicImage, Rgb, RgbImage};
+use std::fmt::Write;
+
+/// upper half block: foreground paints the top pixel, background the bottom one
+const UPPER_HALF: char = '▀';
+
+/// channel levels of the xterm 6x6x6 color cube
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// pixel canvas available to the block renderer, two pixels per cell vertically
+pub fn block_canvas_size() -> Option<(u32, u32)> {
+    // keep the last row free so the prompt does not scroll the image
+    get_terminal_cells().map(|(cols, rows)| (cols, rows.saturating_sub(1).max(1) * 2))
+}
+
+/// render a still image as colored half blocks sized to the terminal
+pub fn blocks_image_convert(img: &DynamicImage, truecolor: bool, size: &SizeOptions) -> Result<String> {
+    let mode = if truecolor { SixelMode::Blocks } else { SixelMode::Blocks256 };
+    let img = scale_to_terminal(img, &mode, size)?;
+
+    Ok(blocks_frame_convert(&img.to_rgb8(), truecolor))
+}
+
+/// render an already scaled frame as colored half blocks
+pub fn blocks_frame_convert(frame: &RgbImage, truecolor: bool) -> String {
+    let (width, height) = frame.dimensions();
+    // roughly 40 bytes per cell when every cell changes color
+    let mut output = String::with_capacity((width * height.div_ceil(2) * 40) as usize);
+
+    for y in (0..height).step_by(2) {
+        if y > 0 {
+            output.push_str("\r\n");
+        }
+
+        let mut last: Option<(Rgb<u8>, Rgb<u8>)> = None;
+        for x in 0..width {
+            let top = *frame.get_pixel(x, y);
+            // odd heights leave the bottom half of the last row black
+            let bottom = if y + 1 < height { *frame.get_pixel(x, y + 1) } else { Rgb([0, 0, 0]) };
+
+            // only emit SGR when the cell colors differ from the previous cell
+            if last != Some((top, bottom)) {
+                push_colors(&mut output, top, bottom, truecolor);
+                last = Some((top, bottom));
+            }
+            output.push(UPPER_HALF);
+        }
+        output.push_str("\x1b[0m");
+    }
+
+    output
+}
+
+fn push_colors(output: &mut String, fg: Rgb<u8>, bg: Rgb<u8>, truecolor: bool) {
+    if truecolor {
+        write!(
+            output,
+            "\x1b[38;2;{};{};{};48;2;{};{};{}m",
+            fg[0], fg[1], fg[2], bg[0], bg[1], bg[2]
+        )
+    } else {
+        write!(output, "\x1b[38;5;{};48;5;{}m", ansi256(fg), ansi256(bg))
+    }
+    .expect("writing into a String cannot fail");
+}
+
+/// nearest xterm 256-color index, from the color cube or the grayscale ramp
+fn ansi256(pixel: Rgb<u8>) -> u8 {
+    let cube_index = |v: u8| -> usize {
+        CUBE_LEVELS
+            .iter()
+            .enumerate()
+            .min_by_key(|&(_, &level)| (level as i32 - v as i32).abs())
+            .map_or(0, |(i, _)| i)
+    };
+    let (r, g, b) = (cube_index(pixel[0]), cube_index(pixel[1]), cube_index(pixel[2]));
+    let cube = [CUBE_LEVELS[r], CUBE_LEVELS[g], CUBE_LEVELS[b]];
+
+    // grayscale ramp 232..=255 covers 8, 18, ..., 238
+    let mean = (pixel[0] as u32 + pixel[1] as u32 + pixel[2] as u32) / 3;
+    let gray_step = ((mean.saturating_sub(3)) / 10).min(23);
+    let gray_level = (8 + gray_step * 10) as u8;
+    let gray = [gray_level; 3];
+
+    let distance = |c: [u8; 3]| -> i32 {
+        (0..3).map(|i| (c[i] as i32 - pixel[i] as i32).pow(2)).sum()
+    };
+
+    if distance(gray) < distance(cube) {
+        232 + gray_step as u8
+    } else {
+        (16 + 36 * r + 6 * g + b) as u8
+    }
+}
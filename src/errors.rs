@@ -5,17 +5,21 @@ pub enum AurenaError {
     FileNotFound { path: String },
     IoError { err: io::Error },
     ImageLoadError { err: image::ImageError },
+    ImageEncodeError { err: image::ImageError },
     VideoOpenError { path: String },
     VideoStreamError { err: ffmpeg_next::Error },
     VideoDecodingError { msg: String },
-    AudioExtractionError { err: Box<dyn Error> },
     AudioPlaybackError { err: rodio::PlayError },
     AudioStreamError { err: rodio::StreamError },
-    AudioDecoderError { err: rodio::decoder::DecoderError },
+    AudioDecoderError { err: ffmpeg_next::Error },
     SixelConversionError { msg: String },
+    UnsupportedMedia { path: String },
     TerminalSizeError,
     InvalidMode { mode: String },
+    InvalidOption { option: String, value: String },
     ProcessingError { msg: String },
+    /// playback was stopped with Ctrl-C or SIGINT
+    Interrupted,
 }
 
 impl Display for AurenaError {
@@ -30,6 +34,9 @@ impl Display for AurenaError {
             AurenaError::ImageLoadError { err } => {
                 writeln!(f, "Failed to load image: {}", err)
             }
+            AurenaError::ImageEncodeError { err } => {
+                writeln!(f, "Failed to encode image: {}", err)
+            }
             AurenaError::VideoOpenError { path } => {
                 writeln!(f, "Failed to open video: {}", path)
             }
@@ -39,9 +46,6 @@ impl Display for AurenaError {
             AurenaError::VideoDecodingError { msg } => {
                 writeln!(f, "Video decoding error: {}", msg)
             }
-            AurenaError::AudioExtractionError { err } => {
-                writeln!(f, "Audio extraction failed: {}", err)
-            }
             AurenaError::AudioPlaybackError { err } => {
                 writeln!(f, "Audio playback error: {}", err)
             }
@@ -54,15 +58,24 @@ impl Display for AurenaError {
             AurenaError::SixelConversionError { msg } => {
                 writeln!(f, "Sixel conversion error: {}", msg)
             }
+            AurenaError::UnsupportedMedia { path } => {
+                writeln!(f, "Unrecognized media: {} is not a supported image, video or audio file", path)
+            }
             AurenaError::TerminalSizeError => {
                 writeln!(f, "Failed to detect terminal size")
             }
             AurenaError::InvalidMode { mode } => {
                 writeln!(f, "Invalid mode: {}", mode)
             }
+            AurenaError::InvalidOption { option, value } => {
+                writeln!(f, "Invalid value for --{}: {}", option, value)
+            }
             AurenaError::ProcessingError { msg } => {
                 writeln!(f, "Processing error: {}", msg)
             }
+            AurenaError::Interrupted => {
+                writeln!(f, "Interrupted")
+            }
         }
     }
 }
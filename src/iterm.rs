@@ -0,0 +1,39 @@
+use crate::errors::{AurenaError, Result};
+use crate::SixelMode;
+use crate::layout::{SizeOptions, scale_to_terminal};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+use image::{DynamicImage, ExtendedColorType, ImageEncoder, RgbImage};
+
+/// convert a still image to an iTerm2 inline image sequence
+pub fn iterm_image_convert(img: &DynamicImage, size: &SizeOptions) -> Result<String> {
+    let img = scale_to_terminal(img, &SixelMode::Iterm, size)?.to_rgb8();
+    let png = encode_png(&img, CompressionType::Default)?;
+    Ok(inline_image(&png, img.width(), img.height()))
+}
+
+/// re-encode a scaled video frame as an inline image; favors speed over size
+pub fn iterm_frame_convert(frame: &RgbImage) -> Result<String> {
+    let png = encode_png(frame, CompressionType::Fast)?;
+    Ok(inline_image(&png, frame.width(), frame.height()))
+}
+
+fn encode_png(img: &RgbImage, compression: CompressionType) -> Result<Vec<u8>> {
+    let mut png = Vec::new();
+    PngEncoder::new_with_quality(&mut png, compression, FilterType::Adaptive)
+        .write_image(img.as_raw(), img.width(), img.height(), ExtendedColorType::Rgb8)
+        .map_err(|e| AurenaError::ImageEncodeError { err: e })?;
+    Ok(png)
+}
+
+/// build an OSC 1337 `File=` sequence displaying `data` at its pixel size
+fn inline_image(data: &[u8], width: u32, height: u32) -> String {
+    let encoded = STANDARD.encode(data);
+    format!(
+        "\x1b]1337;File=inline=1;size={};width={}px;height={}px;preserveAspectRatio=1:{}\x07",
+        data.len(),
+        width,
+        height,
+        encoded,
+    )
+}
@@ -0,0 +1,65 @@
+use crate::errors::{AurenaError, Result};
+use crate::SixelMode;
+use crate::layout::{SizeOptions, scale_to_terminal};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use image::{DynamicImage, ImageFormat, RgbImage};
+use std::io::Cursor;
+
+/// largest base64 payload the protocol allows in one escape sequence
+const CHUNK_SIZE: usize = 4096;
+
+/// placement id reused for every frame so new frames replace the old one
+const PLACEMENT_ID: u32 = 1;
+
+/// image id for this process, so we do not clobber other programs' images
+pub fn image_id() -> u32 {
+    std::process::id() % 0xFF_FFFF + 1
+}
+
+/// convert a still image to a kitty graphics sequence carrying a PNG payload
+pub fn kitty_image_convert(img: &DynamicImage, size: &SizeOptions) -> Result<String> {
+    let img = scale_to_terminal(img, &SixelMode::Kitty, size)?;
+
+    let mut png = Vec::new();
+    img.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .map_err(|e| AurenaError::ImageEncodeError { err: e })?;
+
+    let mut output = String::new();
+    push_transmit(&mut output, &format!("a=T,f=100,i={}", image_id()), &png);
+    Ok(output)
+}
+
+/// encode a video frame as raw 24-bit RGB, replacing the previous frame in place
+pub fn kitty_frame_convert(frame: &RgbImage) -> String {
+    let mut output = String::new();
+    let control = format!(
+        "a=T,f=24,s={},v={},i={},p={},C=1",
+        frame.width(),
+        frame.height(),
+        image_id(),
+        PLACEMENT_ID,
+    );
+    push_transmit(&mut output, &control, frame.as_raw());
+    output
+}
+
+/// append a transmit command, splitting the base64 payload into APC chunks
+fn push_transmit(output: &mut String, control: &str, payload: &[u8]) {
+    let encoded = STANDARD.encode(payload);
+    let chunks: Vec<&[u8]> = encoded.as_bytes().chunks(CHUNK_SIZE).collect();
+    output.reserve(encoded.len() + chunks.len() * 16 + control.len());
+
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = if i + 1 < chunks.len() { 1 } else { 0 };
+        output.push_str("\x1b_G");
+        if i == 0 {
+            // q=2 keeps the terminal from writing replies into our stdin
+            output.push_str(control);
+            output.push_str(",q=2,");
+        }
+        output.push_str(&format!("m={};", more));
+        // base64 output is always ASCII
+        output.push_str(std::str::from_utf8(chunk).unwrap_or_default());
+        output.push_str("\x1b\\");
+    }
+}
@@ -0,0 +1,277 @@
+use crate::SixelMode;
+use crate::blocks::block_canvas_size;
+use crate::errors::{AurenaError, Result};
+use crate::terminal::get_terminal_geometry;
+use image::DynamicImage;
+
+/// one half-block cell holds a 1x2 pixel canvas
+const BLOCK_CELL: (u32, u32) = (1, 2);
+
+/// largest output width or height when the terminal reports no limit
+const MAX_DIMENSION: u32 = 8192;
+
+/// a requested output width or height
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dimension {
+    /// absolute size in pixels ("640" or "640px")
+    Pixels(u32),
+    /// terminal columns for widths, rows for heights ("40c")
+    Cells(u32),
+    /// share of the available terminal area ("50%")
+    Percent(f64),
+}
+
+impl Dimension {
+    pub fn parse(option: &str, s: &str) -> Result<Self> {
+        let invalid = || AurenaError::InvalidOption {
+            option: option.to_string(),
+            value: s.to_string(),
+        };
+
+        let parsed = if let Some(percent) = s.strip_suffix('%') {
+            percent.parse::<f64>().ok().filter(|p| *p > 0.0).map(Dimension::Percent)
+        } else if let Some(cells) = ["cols", "rows", "cells", "c"].iter().find_map(|suffix| s.strip_suffix(suffix)) {
+            cells.parse().ok().filter(|c| *c > 0).map(Dimension::Cells)
+        } else {
+            s.strip_suffix("px").unwrap_or(s).parse().ok().filter(|p| *p > 0).map(Dimension::Pixels)
+        };
+
+        parsed.ok_or_else(invalid)
+    }
+
+    /// resolve to pixels given the pixel size of one cell and the available extent
+    fn to_pixels(self, cell: u32, available: u32) -> u32 {
+        match self {
+            Dimension::Pixels(px) => px,
+            Dimension::Cells(cells) => cells.saturating_mul(cell),
+            Dimension::Percent(percent) => (available as f64 * percent / 100.0) as u32,
+        }
+        .max(1)
+    }
+}
+
+/// how the image is fitted into the target box
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FitMode {
+    /// scale to fit inside the box, keeping the aspect ratio
+    Contain,
+    /// scale to cover the box, keeping the aspect ratio, then crop the overflow
+    Cover,
+    /// stretch to exactly the box size
+    Fill,
+    /// keep the native size, cropping anything beyond the box
+    Original,
+}
+
+impl FitMode {
+    pub fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "contain" => Ok(FitMode::Contain),
+            "cover" => Ok(FitMode::Cover),
+            "fill" | "stretch" => Ok(FitMode::Fill),
+            "none" | "original" => Ok(FitMode::Original),
+            _ => Err(AurenaError::InvalidOption {
+                option: "fit".to_string(),
+                value: s.to_string(),
+            }),
+        }
+    }
+}
+
+/// user sizing controls shared by every output mode
+#[derive(Debug, Clone)]
+pub struct SizeOptions {
+    pub width: Option<Dimension>,
+    pub height: Option<Dimension>,
+    pub fit: FitMode,
+    /// largest sixel image the terminal will draw, from XTSMGRAPHICS; other
+    /// modes and terminals that do not say are held to `MAX_DIMENSION`
+    pub max_size: Option<(u32, u32)>,
+}
+
+impl Default for SizeOptions {
+    fn default() -> Self {
+        SizeOptions { width: None, height: None, fit: FitMode::Contain, max_size: None }
+    }
+}
+
+/// where a source image ends up: its scaled size and the centered crop taken from it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Placement {
+    pub scaled_width: u32,
+    pub scaled_height: u32,
+    pub crop_x: u32,
+    pub crop_y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Placement {
+    fn uncropped(width: u32, height: u32) -> Self {
+        Placement { scaled_width: width, scaled_height: height, crop_x: 0, crop_y: 0, width, height }
+    }
+
+    fn centered_crop(scaled_width: u32, scaled_height: u32, width: u32, height: u32) -> Self {
+        let (width, height) = (width.min(scaled_width), height.min(scaled_height));
+        Placement {
+            scaled_width,
+            scaled_height,
+            crop_x: (scaled_width - width) / 2,
+            crop_y: (scaled_height - height) / 2,
+            width,
+            height,
+        }
+    }
+
+    /// scale and crop an image according to this placement
+    pub fn apply(&self, img: &DynamicImage) -> DynamicImage {
+        let scaled = if (self.scaled_width, self.scaled_height) != (img.width(), img.height()) {
+            img.resize_exact(self.scaled_width, self.scaled_height, image::imageops::FilterType::Lanczos3)
+        } else {
+            img.clone()
+        };
+
+        if (self.width, self.height) != (self.scaled_width, self.scaled_height) {
+            scaled.crop_imm(self.crop_x, self.crop_y, self.width, self.height)
+        } else {
+            scaled
+        }
+    }
+}
+
+/// largest size with the source aspect ratio that fits the bounds, never upscaling
+pub fn fit_within(width: u32, height: u32, max_width: u32, max_height: u32) -> (u32, u32) {
+    let (fit_width, fit_height) = scale_to_box(width, height, max_width, max_height, f64::min);
+    if fit_width > width || fit_height > height {
+        (width, height)
+    } else {
+        (fit_width, fit_height)
+    }
+}
+
+/// decide how a `width` x `height` source is scaled and cropped
+///
+/// `area` is the visible pixel area and `cell` the pixel size of one terminal cell
+pub fn compute_placement(
+    width: u32,
+    height: u32,
+    area: (u32, u32),
+    cell: (u32, u32),
+    size: &SizeOptions,
+) -> Placement {
+    if width == 0 || height == 0 {
+        return Placement::uncropped(width, height);
+    }
+
+    let max_size = size.max_size.unwrap_or((MAX_DIMENSION, MAX_DIMENSION));
+    let area = (area.0.min(max_size.0), area.1.min(max_size.1));
+
+    let requested_width = size.width.map(|w| w.to_pixels(cell.0, area.0));
+    let requested_height = size.height.map(|h| h.to_pixels(cell.1, area.1));
+
+    // a single requested dimension derives the other from the source aspect ratio
+    let (box_width, box_height) = match (requested_width, requested_height) {
+        (Some(w), Some(h)) => (w, h),
+        (Some(w), None) => (w, ((w as f64 * height as f64 / width as f64) as u32).max(1)),
+        (None, Some(h)) => (((h as f64 * width as f64 / height as f64) as u32).max(1), h),
+        (None, None) => area,
+    };
+    let (box_width, box_height) = (box_width.min(max_size.0), box_height.min(max_size.1));
+    let explicit = requested_width.is_some() || requested_height.is_some();
+
+    match size.fit {
+        // only grow small images when the user asked for a size
+        FitMode::Contain if !explicit => {
+            let (w, h) = fit_within(width, height, box_width, box_height);
+            Placement::uncropped(w, h)
+        }
+        FitMode::Contain => {
+            let (w, h) = scale_to_box(width, height, box_width, box_height, f64::min);
+            Placement::uncropped(w, h)
+        }
+        FitMode::Cover => {
+            let (w, h) = scale_to_box(width, height, box_width, box_height, f64::max);
+            // extreme aspect ratios would scale far past the box before being cropped;
+            // they are squeezed instead
+            let (w, h) = (w.min(MAX_DIMENSION.max(box_width)), h.min(MAX_DIMENSION.max(box_height)));
+            Placement::centered_crop(w, h, box_width, box_height)
+        }
+        FitMode::Fill => Placement::uncropped(box_width, box_height),
+        FitMode::Original => Placement::centered_crop(width, height, box_width, box_height),
+    }
+}
+
+/// placement of a source inside the terminal, in the pixel units of the output mode
+pub fn terminal_placement(mode: &SixelMode, width: u32, height: u32, size: &SizeOptions) -> Result<Placement> {
+    let (area, cell) = match mode {
+        SixelMode::Blocks | SixelMode::Blocks256 => {
+            let canvas = block_canvas_size()
+                .ok_or(AurenaError::TerminalSizeError)?;
+            (canvas, BLOCK_CELL)
+        }
+        _ => {
+            let geometry = get_terminal_geometry()
+                .ok_or(AurenaError::TerminalSizeError)?;
+            (geometry.image_area(), (geometry.cell_width, geometry.cell_height))
+        }
+    };
+
+    // the sixel geometry only limits sixel images
+    let sixel = matches!(mode, SixelMode::Color | SixelMode::Monochrome);
+    let size = SizeOptions { max_size: size.max_size.filter(|_| sixel), ..size.clone() };
+    Ok(compute_placement(width, height, area, cell, &size))
+}
+
+/// scale and crop an image for the visible terminal area
+pub fn scale_to_terminal(img: &DynamicImage, mode: &SixelMode, size: &SizeOptions) -> Result<DynamicImage> {
+    let placement = terminal_placement(mode, img.width(), img.height(), size)?;
+    Ok(placement.apply(img))
+}
+
+/// scale preserving aspect ratio; `pick` chooses between the two axis ratios
+fn scale_to_box(width: u32, height: u32, max_width: u32, max_height: u32, pick: fn(f64, f64) -> f64) -> (u32, u32) {
+    if width == 0 || height == 0 {
+        return (width, height);
+    }
+
+    let scale = pick(
+        max_width as f64 / width as f64,
+        max_height as f64 / height as f64,
+    );
+
+    (
+        ((width as f64 * scale).round() as u32).max(1),
+        ((height as f64 * scale).round() as u32).max(1),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn huge_sizes_are_bounded() {
+        let size = SizeOptions {
+            width: Some(Dimension::Cells(u32::MAX)),
+            height: Some(Dimension::Pixels(u32::MAX)),
+            fit: FitMode::Fill,
+            max_size: None,
+        };
+        let placement = compute_placement(640, 480, (1600, 900), (10, 20), &size);
+        assert_eq!((placement.width, placement.height), (MAX_DIMENSION, MAX_DIMENSION));
+
+        let size = SizeOptions { max_size: Some((1000, 1000)), ..size };
+        let placement = compute_placement(640, 480, (1600, 900), (10, 20), &size);
+        assert_eq!((placement.width, placement.height), (1000, 1000));
+    }
+
+    #[test]
+    fn covering_with_extreme_aspect_ratios_stays_bounded() {
+        let size = SizeOptions { fit: FitMode::Cover, ..SizeOptions::default() };
+        for (width, height) in [(10, 100_000), (100_000, 10)] {
+            let placement = compute_placement(width, height, (1600, 900), (10, 20), &size);
+            assert_eq!((placement.width, placement.height), (1600, 900));
+            assert!(placement.scaled_width <= MAX_DIMENSION && placement.scaled_height <= MAX_DIMENSION);
+        }
+    }
+}
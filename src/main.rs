@@ -1,17 +1,37 @@
 mod errors;
 mod terminal;
+mod layout;
 mod sixel;
+mod kitty;
+mod iterm;
+mod blocks;
 mod media;
 
 use errors::{AurenaError, Result};
-use media::{image::handle_image, video::handle_video};
+use media::{
+    animation::handle_animation,
+    audio::handle_audio,
+    detect::{MediaKind, detect_media},
+    image::handle_image,
+    input::{MediaInput, STDIN_PATH},
+    playback::PlaybackRange,
+    video::handle_video,
+};
+use sixel::{SixelOptions, color::{MAX_PALETTE_COLORS, PaletteKind, get_palette}, dither::DitherMode, metric::ColorMetric};
+use layout::{Dimension, FitMode, SizeOptions};
+use terminal::{TerminalCapabilities, install_restore_handlers, probe_capabilities, reset_terminal};
 use clap::Parser;
 use std::path::Path;
+use std::process::ExitCode;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum SixelMode {
     Color,
     Monochrome,
+    Kitty,
+    Iterm,
+    Blocks,
+    Blocks256,
 }
 
 impl SixelMode {
@@ -19,36 +39,148 @@ impl SixelMode {
         match s {
             "sixel-color" | "sixel" => Ok(SixelMode::Color),
             "sixel-mono" => Ok(SixelMode::Monochrome),
+            "kitty" => Ok(SixelMode::Kitty),
+            "iterm" | "iterm2" => Ok(SixelMode::Iterm),
+            "blocks" => Ok(SixelMode::Blocks),
+            "blocks-256" => Ok(SixelMode::Blocks256),
             _ => Err(AurenaError::InvalidMode {
                 mode: s.to_string(),
             }),
         }
     }
+
+    /// pick the best backend the terminal supports
+    pub fn from_capabilities(caps: &TerminalCapabilities) -> Self {
+        if caps.kitty {
+            SixelMode::Kitty
+        } else if caps.iterm {
+            SixelMode::Iterm
+        } else if caps.sixel {
+            SixelMode::Color
+        } else if caps.truecolor {
+            SixelMode::Blocks
+        } else {
+            SixelMode::Blocks256
+        }
+    }
 }
 
 #[derive(Parser)]
 #[command(name = "aurena", version = "0.1.0", author = "Shivani Palya")]
 struct CLI {
-    /// input image or video file
+    /// input image or video file, or '-' to read from stdin
     #[arg(long, value_name = "FILE")]
     input: String,
 
-    /// output mode: 'sixel-color' or 'sixel-mono'
-    #[arg(long, value_name = "MODE")]
+    /// output mode: 'auto', 'sixel-color', 'sixel-mono', 'kitty', 'iterm', 'blocks' or 'blocks-256'
+    #[arg(long, value_name = "MODE", default_value = "auto")]
     mode: String,
 
     /// enable audio playback for videos
     #[arg(long, action = clap::ArgAction::SetTrue)]
     audio: bool,
+
+    /// disable run-length (DECGRI) compression of sixel output
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    no_rle: bool,
+
+    /// repaint every video frame in full instead of only the rows that changed
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    no_delta: bool,
+
+    /// color palette: 'fixed', 'median-cut' or 'octree'
+    #[arg(long, value_name = "PALETTE", default_value = "fixed")]
+    palette: String,
+
+    /// maximum number of colors for adaptive palettes (2-256)
+    #[arg(long, value_name = "N", default_value_t = MAX_PALETTE_COLORS)]
+    colors: usize,
+
+    /// color distance: 'rgb', 'linear-rgb', 'cie76', 'ciede2000' or 'oklab'
+    #[arg(long, value_name = "METRIC", default_value = "rgb")]
+    metric: String,
+
+    /// dithering: 'none', 'floyd-steinberg', 'atkinson', 'jarvis', 'bayer2', 'bayer4' or 'bayer8'
+    #[arg(long, value_name = "DITHER", default_value = "none")]
+    dither: String,
+
+    /// output width: pixels ('640'), columns ('40c') or percent of the terminal ('50%')
+    #[arg(long, value_name = "WIDTH")]
+    width: Option<String>,
+
+    /// output height: pixels ('480'), rows ('20c') or percent of the terminal ('50%')
+    #[arg(long, value_name = "HEIGHT")]
+    height: Option<String>,
+
+    /// fit mode: 'contain', 'cover', 'fill' (stretch) or 'none' (original size)
+    #[arg(long, value_name = "FIT", default_value = "contain")]
+    fit: String,
+
+    /// start video playback at this time: seconds ('90.5') or '[hh:]mm:ss'
+    #[arg(long, value_name = "TIME")]
+    start: Option<String>,
+
+    /// stop video playback at this time
+    #[arg(long, value_name = "TIME", conflicts_with = "duration")]
+    end: Option<String>,
+
+    /// stop video playback after playing this long
+    #[arg(long, value_name = "TIME")]
+    duration: Option<String>,
 }
 
 impl CLI {
-    fn mode(&self) -> Result<SixelMode> {
-        SixelMode::from_str(&self.mode)
+    fn mode(&self, caps: &TerminalCapabilities) -> Result<SixelMode> {
+        match self.mode.as_str() {
+            "auto" => Ok(SixelMode::from_capabilities(caps)),
+            mode => SixelMode::from_str(mode),
+        }
+    }
+
+    fn playback_range(&self) -> Result<PlaybackRange> {
+        PlaybackRange::parse(self.start.as_deref(), self.end.as_deref(), self.duration.as_deref())
+    }
+
+    fn sixel_options(&self) -> Result<SixelOptions> {
+        Ok(SixelOptions {
+            compress: !self.no_rle,
+            palette: PaletteKind::from_str(&self.palette)?,
+            colors: self.colors,
+            metric: ColorMetric::from_str(&self.metric)?,
+            dither: DitherMode::from_str(&self.dither)?,
+            size: SizeOptions {
+                width: self.width.as_deref().map(|w| Dimension::parse("width", w)).transpose()?,
+                height: self.height.as_deref().map(|h| Dimension::parse("height", h)).transpose()?,
+                fit: FitMode::from_str(&self.fit)?,
+                max_size: None,
+            },
+            delta: !self.no_delta,
+        })
     }
 }
 
-fn main() -> Result<()> {
+/// exit status for playback stopped by Ctrl-C, as shells report a SIGINT
+const INTERRUPTED_EXIT_CODE: u8 = 130;
+
+fn main() -> ExitCode {
+    install_restore_handlers();
+
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(AurenaError::Interrupted) => {
+            reset_terminal();
+            ExitCode::from(INTERRUPTED_EXIT_CODE)
+        }
+        Err(e) => {
+            // an error can cut off output mid-sequence
+            reset_terminal();
+            eprintln!("Error: {:?}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run() -> Result<()> {
     // initialize FFmpeg
     ffmpeg_next::init().map_err(|e| AurenaError::ProcessingError {
         msg: format!("FFmpeg initialization failed: {}", e),
@@ -57,24 +189,38 @@ fn main() -> Result<()> {
     let args = CLI::parse();
     
     // validate input file exists
-    if !Path::new(&args.input).exists() {
+    if args.input != STDIN_PATH && !Path::new(&args.input).exists() {
         return Err(AurenaError::FileNotFound {
             path: args.input.clone(),
         });
     }
 
-    let sixel_mode = args.mode()?;
+    let input = MediaInput::open(&args.input)?;
+    let media_kind = detect_media(&input)?;
+
+    let caps = if args.mode == "auto" { probe_capabilities() } else { TerminalCapabilities::default() };
+    let sixel_mode = args.mode(&caps)?;
+    let mut options = args.sixel_options()?;
+    let range = args.playback_range()?;
+
+    // never ask for more colors than the terminal has registers
+    if let Some(registers) = caps.color_registers {
+        let registers = registers as usize;
+        options.colors = options.colors.min(registers);
+        // the hand-picked palette cannot shrink, so build one that fits instead
+        if options.palette == PaletteKind::Fixed && registers < get_palette().len() {
+            options.palette = PaletteKind::MedianCut;
+        }
+    }
+    options.size.max_size = caps.sixel_geometry;
 
-    // route to appropriate handler based on file type
-    if is_image_file(&args.input) {
-        handle_image(&args.input, sixel_mode)?;
-    } else {
-        handle_video(&args.input, args.audio, sixel_mode)?;
+    // route to appropriate handler based on what the file contains
+    match media_kind {
+        MediaKind::Image => handle_image(&input, sixel_mode, &options)?,
+        MediaKind::Animation => handle_animation(&input, sixel_mode, &options)?,
+        MediaKind::Video => handle_video(&input, args.audio, sixel_mode, &options, range)?,
+        MediaKind::Audio => handle_audio(&args.input)?,
     }
 
     Ok(())
-}
-
-fn is_image_file(path: &str) -> bool {
-    path.ends_with(".png") || path.ends_with(".jpeg") || path.ends_with(".jpg")
 }
\ No newline at end of file
@@ -0,0 +1,202 @@
+use crate::errors::{AurenaError, Result};
+use crate::SixelMode;
+use crate::layout::terminal_placement;
+use crate::terminal::{InterruptWatch, clear_screen};
+use crate::sixel::{convert_image, SixelOptions};
+use crate::media::frame::FrameEncoder;
+use crate::media::input::MediaInput;
+use image::codecs::{gif::GifDecoder, png::PngDecoder, webp::WebPDecoder};
+use image::{AnimationDecoder, DynamicImage, Frames, ImageFormat, RgbImage, RgbaImage};
+use std::io::{self, BufWriter, Cursor, Write};
+use std::time::{Duration, Instant};
+
+/// delays this short are treated as "unspecified", as browsers do for GIF
+const MIN_FRAME_DELAY: Duration = Duration::from_millis(20);
+const DEFAULT_FRAME_DELAY: Duration = Duration::from_millis(100);
+
+/// encoded frames are replayed from memory on later loops up to this size
+const MAX_CACHE_BYTES: usize = 256 * 1024 * 1024;
+
+/// play an animated image, honoring frame delays and its loop count
+pub fn handle_animation(input: &MediaInput, sixel_mode: SixelMode, options: &SixelOptions) -> Result<()> {
+    let data = input.read_all()?;
+    let format = animation_format(&data)
+        .ok_or_else(|| AurenaError::ProcessingError { msg: format!("Not an animated image: {}", input.path) })?;
+    let plays = play_count(format, &data);
+
+    let mut frames = decode_frames(format, &data)?.peekable();
+    let first = match frames.next() {
+        Some(frame) => frame.map_err(|e| AurenaError::ImageLoadError { err: e })?,
+        None => return Ok(()),
+    };
+
+    // a single frame is just a still image
+    if frames.peek().is_none() {
+        let img = DynamicImage::ImageRgb8(flatten_alpha(first.into_buffer()));
+        let mut out = io::stdout().lock();
+        convert_image(&img, sixel_mode, options, &mut out)?;
+        return writeln!(out).and_then(|()| out.flush()).map_err(|e| AurenaError::IoError { err: e });
+    }
+
+    let (width, height) = first.buffer().dimensions();
+    let placement = terminal_placement(&sixel_mode, width, height, &options.size)?;
+    let mut encoder = FrameEncoder::new(sixel_mode, options, placement.width, placement.height);
+    let mut first = Some(first);
+    let mut out = BufWriter::new(io::stdout().lock());
+    // frames are drawn over each other from the top left, so start from an empty screen
+    clear_screen(&mut out).map_err(|e| AurenaError::IoError { err: e })?;
+
+    let mut cache: Vec<(Vec<u8>, Duration)> = Vec::new();
+    let mut cache_bytes = 0;
+    let mut cache_complete = false;
+    let mut deadline = Instant::now();
+    let mut played = 0;
+    let interrupts = InterruptWatch::start();
+
+    loop {
+        if cache_complete {
+            for (frame_data, delay) in &cache {
+                if interrupts.interrupted() {
+                    return Err(AurenaError::Interrupted);
+                }
+                encoder.present(&mut out, frame_data)?;
+                wait_until(&mut deadline, *delay);
+            }
+        } else {
+            let pass = if played == 0 {
+                Box::new(first.take().map(Ok).into_iter().chain(&mut frames)) as Box<dyn Iterator<Item = _>>
+            } else {
+                Box::new(decode_frames(format, &data)?)
+            };
+
+            let mut caching = played == 0;
+            for frame in pass {
+                if interrupts.interrupted() {
+                    return Err(AurenaError::Interrupted);
+                }
+                let frame = frame.map_err(|e| AurenaError::ImageLoadError { err: e })?;
+                let delay = frame_delay(&frame);
+
+                let rgb = DynamicImage::ImageRgb8(flatten_alpha(frame.into_buffer()));
+                let frame_data = encoder.encode(placement.apply(&rgb).to_rgb8())?;
+                encoder.present(&mut out, &frame_data)?;
+
+                if caching {
+                    cache_bytes += frame_data.len();
+                    caching = cache_bytes <= MAX_CACHE_BYTES;
+                    if caching {
+                        cache.push((frame_data, delay));
+                    } else {
+                        cache = Vec::new();
+                    }
+                }
+                wait_until(&mut deadline, delay);
+            }
+            cache_complete = caching;
+        }
+
+        played += 1;
+        if plays.is_some_and(|plays| played >= plays) {
+            break;
+        }
+    }
+
+    writeln!(out).and_then(|()| out.flush()).map_err(|e| AurenaError::IoError { err: e })
+}
+
+/// detect the animated formats we play natively from the file contents
+pub fn animation_format(data: &[u8]) -> Option<ImageFormat> {
+    match image::guess_format(data).ok()? {
+        ImageFormat::Gif => Some(ImageFormat::Gif),
+        // APNG carries an acTL chunk ahead of the image data
+        ImageFormat::Png if find_chunk(data, b"acTL").is_some_and(|actl| {
+            find_chunk(data, b"IDAT").is_none_or(|idat| actl < idat)
+        }) => Some(ImageFormat::Png),
+        // extended WebP sets the animation flag in its VP8X header
+        ImageFormat::WebP if data.get(12..16) == Some(&b"VP8X"[..]) && data.get(20).is_some_and(|flags| flags & 0x02 != 0) => {
+            Some(ImageFormat::WebP)
+        }
+        _ => None,
+    }
+}
+
+fn decode_frames(format: ImageFormat, data: &[u8]) -> Result<Frames<'_>> {
+    let reader = Cursor::new(data);
+    let frames = match format {
+        ImageFormat::Gif => GifDecoder::new(reader).map(|d| d.into_frames()),
+        ImageFormat::Png => PngDecoder::new(reader)
+            .and_then(|d| d.apng())
+            .map(|d| d.into_frames()),
+        _ => WebPDecoder::new(reader).map(|d| d.into_frames()),
+    };
+
+    frames.map_err(|e| AurenaError::ImageLoadError { err: e })
+}
+
+/// total number of plays from the container's loop count; `None` loops forever
+fn play_count(format: ImageFormat, data: &[u8]) -> Option<u32> {
+    let le16 = |at: usize| data.get(at..at + 2).map(|b| u16::from_le_bytes([b[0], b[1]]) as u32);
+
+    match format {
+        // NETSCAPE2.0 application extension: sub-block size 3, id 1, u16 repeat count;
+        // the count is extra loops after the first play, and no extension plays once
+        ImageFormat::Gif => {
+            let Some(pos) = find_chunk(data, b"NETSCAPE2.0").or_else(|| find_chunk(data, b"ANIMEXTS1.0")) else {
+                return Some(1);
+            };
+            match le16(pos + 13) {
+                Some(0) => None,
+                Some(repeats) => Some(repeats + 1),
+                None => Some(1),
+            }
+        }
+        // acTL: u32 frame count, then u32 play count, big endian
+        ImageFormat::Png => {
+            let plays = find_chunk(data, b"acTL")
+                .and_then(|pos| data.get(pos + 8..pos + 12))
+                .map_or(1, |b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]));
+            (plays != 0).then_some(plays)
+        }
+        // ANIM: u32 chunk size, u32 background color, u16 loop count
+        _ => {
+            let plays = find_chunk(data, b"ANIM").and_then(|pos| le16(pos + 12)).unwrap_or(1);
+            (plays != 0).then_some(plays)
+        }
+    }
+}
+
+fn find_chunk(data: &[u8], tag: &[u8]) -> Option<usize> {
+    data.windows(tag.len()).position(|window| window == tag)
+}
+
+fn frame_delay(frame: &image::Frame) -> Duration {
+    let (numer, denom) = frame.delay().numer_denom_ms();
+    let delay = Duration::from_secs_f64(numer as f64 / denom.max(1) as f64 / 1000.0);
+    if delay < MIN_FRAME_DELAY { DEFAULT_FRAME_DELAY } else { delay }
+}
+
+/// sleep until the frame's display time is over, without accumulating drift
+fn wait_until(deadline: &mut Instant, delay: Duration) {
+    *deadline += delay;
+    let now = Instant::now();
+    if *deadline > now {
+        std::thread::sleep(*deadline - now);
+    } else {
+        // running behind; restart the schedule instead of rushing through frames
+        *deadline = now;
+    }
+}
+
+/// composite transparent pixels over black, since frames are drawn over each other
+fn flatten_alpha(img: RgbaImage) -> RgbImage {
+    let (width, height) = img.dimensions();
+    let pixels = img
+        .pixels()
+        .flat_map(|p| {
+            let alpha = p[3] as u16;
+            [0, 1, 2].map(|c| ((p[c] as u16 * alpha + 127) / 255) as u8)
+        })
+        .collect();
+
+    RgbImage::from_raw(width, height, pixels).unwrap_or_else(|| RgbImage::new(width, height))
+}
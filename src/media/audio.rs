@@ -1,66 +1,406 @@
 use crate::errors::{AurenaError, Result};
-use rodio::{Decoder, OutputStream, Sink, Source};
+use crate::media::input::start_offset;
+use crate::terminal::InterruptWatch;
+use ffmpeg_next::{
+    self as ffmpeg,
+    format::{context::Input, sample::Type as SampleType, Sample as SampleFormat},
+    software::resampling::Context as Resampler,
+    util::frame::audio::Audio,
+    ChannelLayout, Packet,
+};
+use rodio::{OutputStream, OutputStreamHandle, Sample, Sink, Source};
 use std::{
-    fs::File,
-    io::BufReader,
-    sync::Arc,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, SyncSender},
+    },
     thread,
-    time::{Duration, Instant},
+    time::Duration,
 };
 
+/// decoded frames buffered ahead of the output device
+const DECODE_AHEAD: usize = 32;
+
+/// how often audio-only playback checks for the end or an interrupt
+const END_POLL: Duration = Duration::from_millis(100);
+
+/// audio track of a video, decoded on a background thread
+pub struct AudioPlayer {
+    sink: Sink,
+    handle: OutputStreamHandle,
+    _stream: OutputStream,
+    audio_path: String,
+    /// where the current source started, and how far it has been played since
+    start: Duration,
+    played: PlayedSamples,
+}
+
+impl AudioPlayer {
+    pub fn sink(&self) -> &Sink {
+        &self.sink
+    }
+
+    /// media time of the audio handed to the output device
+    pub fn position(&self) -> Duration {
+        self.start + self.played.duration()
+    }
+
+    /// restart the track at `position`, keeping the pause state and speed
+    pub fn seek(&mut self, position: Duration) -> Result<()> {
+        // rodio cannot seek a playing source, so queue a fresh one on a new sink
+        let sink = Sink::try_new(&self.handle)
+            .map_err(|e| AurenaError::AudioPlaybackError { err: e })?;
+        sink.set_speed(self.sink.speed());
+        if self.sink.is_paused() {
+            sink.pause();
+        }
+        let (source, played) = PlayedSamples::track(open_source(&self.audio_path, position)?);
+        sink.append(source);
+
+        self.sink = sink;
+        self.start = position;
+        self.played = played;
+        Ok(())
+    }
+}
+
+impl Drop for AudioPlayer {
+    fn drop(&mut self) {
+        // dropping the source disconnects its decoder thread, which then exits
+        self.sink.stop();
+    }
+}
+
 /// setup audio playback for a video file
-pub fn audio_setup(video_path: &str) -> Result<(Arc<Sink>, OutputStream)> {
+pub fn audio_setup(video_path: &str) -> Result<AudioPlayer> {
     println!("Setting up audio for: {}", video_path);
     
-    let audio_path = find_or_extract_audio(video_path)?;
+    let audio_path = find_audio(video_path);
     
     let (stream, stream_handle) = OutputStream::try_default()
         .map_err(|e| AurenaError::AudioStreamError { err: e })?;
     
-    let sink = Arc::new(Sink::try_new(&stream_handle)
-        .map_err(|e| AurenaError::AudioPlaybackError { err: e })?);
+    let sink = Sink::try_new(&stream_handle)
+        .map_err(|e| AurenaError::AudioPlaybackError { err: e })?;
     
-    let file = File::open(&audio_path)
-        .map_err(|e| AurenaError::IoError { err: e })?;
-    
-    let source = Decoder::new(BufReader::new(file))
-        .map_err(|e| AurenaError::AudioDecoderError { err: e })?;
+    let source = open_source(&audio_path, Duration::ZERO)?;
     
     println!("Audio: {} channels, {} Hz", source.channels(), source.sample_rate());
     
+    let (source, played) = PlayedSamples::track(source);
     sink.append(source);
     sink.pause();
     
-    // cleanup handling for temporary files
-    if audio_path != video_path && audio_path.contains("temp_audio") {
-        let audio_path_clone = audio_path.clone();
-        let sink_clone = Arc::clone(&sink);
-        thread::spawn(move || {
-            let cleanup_start = Instant::now();
-            let max_wait = Duration::from_secs(300);
-            
-            loop {
-                if sink_clone.empty() || cleanup_start.elapsed() > max_wait {
-                    break;
+    Ok(AudioPlayer {
+        sink,
+        handle: stream_handle,
+        _stream: stream,
+        audio_path,
+        start: Duration::ZERO,
+        played,
+    })
+}
+
+/// shared count of the samples rodio has pulled from a source
+///
+/// rodio 0.17 cannot report a playback position, so the source counts for it
+struct PlayedSamples {
+    count: Arc<AtomicU64>,
+    samples_per_second: u64,
+}
+
+impl PlayedSamples {
+    fn track<S>(source: S) -> (CountingSource<S>, Self)
+    where
+        S: Source,
+        S::Item: Sample,
+    {
+        let count = Arc::new(AtomicU64::new(0));
+        let samples_per_second = (source.channels() as u64 * source.sample_rate() as u64).max(1);
+        let counting = CountingSource { inner: source, count: Arc::clone(&count) };
+        (counting, PlayedSamples { count, samples_per_second })
+    }
+
+    fn duration(&self) -> Duration {
+        let samples = self.count.load(Ordering::Relaxed);
+        Duration::from_secs_f64(samples as f64 / self.samples_per_second as f64)
+    }
+}
+
+struct CountingSource<S> {
+    inner: S,
+    count: Arc<AtomicU64>,
+}
+
+impl<S> Iterator for CountingSource<S>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    type Item = S::Item;
+
+    fn next(&mut self) -> Option<S::Item> {
+        let sample = self.inner.next()?;
+        self.count.fetch_add(1, Ordering::Relaxed);
+        Some(sample)
+    }
+}
+
+impl<S> Source for CountingSource<S>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
+
+/// play an audio-only file to the end
+pub fn handle_audio(path: &str) -> Result<()> {
+    let player = audio_setup(path)?;
+    let interrupts = InterruptWatch::start();
+    player.sink().play();
+    while !player.sink().empty() {
+        if interrupts.interrupted() {
+            return Err(AurenaError::Interrupted);
+        }
+        thread::sleep(END_POLL);
+    }
+    Ok(())
+}
+
+/// start decoding the audio stream of `path` from `position` on a background thread
+fn open_source(path: &str, position: Duration) -> Result<StreamSource> {
+    let (ready_tx, ready_rx) = mpsc::channel();
+    let (samples_tx, samples_rx) = mpsc::sync_channel(DECODE_AHEAD);
+    let path = path.to_string();
+
+    thread::spawn(move || match AudioDecoder::open(&path) {
+        Ok(decoder) => {
+            let _ = ready_tx.send(Ok((decoder.channels, decoder.sample_rate)));
+            decoder.run(position, samples_tx);
+        }
+        Err(e) => {
+            let _ = ready_tx.send(Err(e));
+        }
+    });
+
+    let (channels, sample_rate) = ready_rx
+        .recv()
+        .unwrap_or(Err(ffmpeg::Error::Bug))
+        .map_err(|e| AurenaError::AudioDecoderError { err: e })?;
+
+    Ok(StreamSource {
+        samples: samples_rx,
+        chunk: Vec::new().into_iter(),
+        channels,
+        sample_rate,
+    })
+}
+
+/// interleaved f32 samples arriving from the decoder thread
+struct StreamSource {
+    samples: Receiver<Vec<f32>>,
+    chunk: std::vec::IntoIter<f32>,
+    channels: u16,
+    sample_rate: u32,
+}
+
+impl Iterator for StreamSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        loop {
+            if let Some(sample) = self.chunk.next() {
+                return Some(sample);
+            }
+            // the decoder hangs up at the end of the stream
+            self.chunk = self.samples.recv().ok()?.into_iter();
+        }
+    }
+}
+
+impl Source for StreamSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        // everything is resampled to one format up front
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// demuxes and decodes one audio stream into packed f32 at its own rate
+struct AudioDecoder {
+    ictx: Input,
+    decoder: ffmpeg::decoder::Audio,
+    stream_index: usize,
+    time_base: f64,
+    /// start time of the input, subtracted from frame timestamps
+    start_offset: f64,
+    resampler: Option<Resampler>,
+    channels: u16,
+    sample_rate: u32,
+}
+
+impl AudioDecoder {
+    fn open(path: &str) -> std::result::Result<Self, ffmpeg::Error> {
+        ffmpeg::init()?;
+        let ictx = ffmpeg::format::input(&path)?;
+        let stream = ictx
+            .streams()
+            .best(ffmpeg::media::Type::Audio)
+            .ok_or(ffmpeg::Error::StreamNotFound)?;
+
+        let stream_index = stream.index();
+        let time_base = f64::from(stream.time_base());
+        let start_offset = start_offset(&ictx);
+        let decoder = ffmpeg::codec::context::Context::from_parameters(stream.parameters())?
+            .decoder()
+            .audio()?;
+        let (channels, sample_rate) = (decoder.channels(), decoder.rate());
+
+        Ok(AudioDecoder {
+            ictx,
+            decoder,
+            stream_index,
+            time_base,
+            start_offset,
+            resampler: None,
+            channels,
+            sample_rate,
+        })
+    }
+
+    /// decode from `position` to the end, or until the source is dropped
+    fn run(mut self, position: Duration, samples: SyncSender<Vec<f32>>) {
+        // land on the keyframe before the target, then trim up to it
+        let mut seek_target = None;
+        if !position.is_zero() {
+            let timestamp = ((position.as_secs_f64() + self.start_offset) * f64::from(ffmpeg::ffi::AV_TIME_BASE)) as i64;
+            let _ = self.ictx.seek(timestamp, ..timestamp);
+            seek_target = Some(position.as_secs_f64());
+        }
+
+        let mut frame = Audio::empty();
+        loop {
+            let mut packet = Packet::empty();
+            match packet.read(&mut self.ictx) {
+                Ok(()) => {}
+                Err(ffmpeg::Error::Eof) => break,
+                Err(_) => continue,
+            }
+            if packet.stream() != self.stream_index || self.decoder.send_packet(&packet).is_err() {
+                continue;
+            }
+
+            while self.decoder.receive_frame(&mut frame).is_ok() {
+                if !self.forward(&frame, &mut seek_target, &samples) {
+                    return;
                 }
-                thread::sleep(Duration::from_secs(1));
             }
-            
-            thread::sleep(Duration::from_secs(5));
-            
-            if let Err(e) = std::fs::remove_file(&audio_path_clone) {
-                eprintln!("Cleanup failed: {}", e);
-            } else {
-                println!("Temp audio cleaned up");
+        }
+
+        // drain the decoder, then whatever the resampler still holds
+        let _ = self.decoder.send_eof();
+        while self.decoder.receive_frame(&mut frame).is_ok() {
+            if !self.forward(&frame, &mut seek_target, &samples) {
+                return;
+            }
+        }
+        if let Some(resampler) = self.resampler.as_mut() {
+            let mut output = Audio::empty();
+            if resampler.flush(&mut output).is_ok() && output.samples() > 0 {
+                let _ = samples.send(interleaved_samples(&output, self.channels, 0));
             }
-        });
+        }
     }
-    
-    Ok((sink, stream))
+
+    /// pass a decoded frame on to the source; false once it is gone, or when the
+    /// frame cannot be resampled, which ends the stream instead of leaving a silent gap
+    fn forward(&mut self, frame: &Audio, seek_target: &mut Option<f64>, samples: &SyncSender<Vec<f32>>) -> bool {
+        match self.convert(frame, seek_target) {
+            Ok(Some(chunk)) => samples.send(chunk).is_ok(),
+            Ok(None) => true,
+            Err(e) => {
+                eprintln!("Audio resampling failed: {}", e);
+                false
+            }
+        }
+    }
+
+    /// resample a decoded frame, dropping samples before the seek target
+    fn convert(&mut self, frame: &Audio, seek_target: &mut Option<f64>) -> std::result::Result<Option<Vec<f32>>, ffmpeg::Error> {
+        let mut skip = 0;
+        if let Some(target) = *seek_target {
+            let start = frame.timestamp().map_or(target, |ts| ts as f64 * self.time_base - self.start_offset);
+            let end = start + frame.samples() as f64 / frame.rate().max(1) as f64;
+            if end <= target {
+                return Ok(None);
+            }
+            skip = ((target - start).max(0.0) * frame.rate() as f64).round() as usize;
+            *seek_target = None;
+        }
+
+        // the decoder may switch formats mid-stream, so the resampler follows each frame
+        let input = (frame.format(), frame.channel_layout(), frame.rate());
+        let resampler = match self.resampler.take() {
+            Some(resampler) if {
+                let definition = resampler.input();
+                (definition.format, definition.channel_layout, definition.rate) == input
+            } => resampler,
+            _ => Resampler::get(
+                input.0,
+                input.1,
+                input.2,
+                SampleFormat::F32(SampleType::Packed),
+                ChannelLayout::default(self.channels as i32),
+                self.sample_rate,
+            )?,
+        };
+
+        let mut output = Audio::empty();
+        self.resampler.insert(resampler).run(frame, &mut output)?;
+        Ok(Some(interleaved_samples(&output, self.channels, skip)))
+    }
+}
+
+/// the samples of a packed f32 frame, minus the first `skip` per channel
+fn interleaved_samples(frame: &Audio, channels: u16, skip: usize) -> Vec<f32> {
+    let channels = channels as usize;
+    let len = frame.samples() * channels;
+    frame.data(0)[..len * 4]
+        .chunks_exact(4)
+        .skip(skip * channels)
+        .map(|bytes| f32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .collect()
 }
 
-/// find existing audio file or extract from video
-fn find_or_extract_audio(video_path: &str) -> Result<String> {
+/// a separate audio file next to the video, or the video itself
+fn find_audio(video_path: &str) -> String {
     let video_stem = std::path::Path::new(video_path).file_stem()
         .and_then(|s| s.to_str())
         .unwrap_or("video");
@@ -73,61 +413,11 @@ fn find_or_extract_audio(video_path: &str) -> Result<String> {
     // look for existing audio files
     for ext in &audio_extensions {
         let audio_path = video_dir.join(format!("{}.{}", video_stem, ext));
-        if audio_path.exists() {
+        if audio_path.exists() && audio_path != std::path::Path::new(video_path) {
             println!("Found separate audio: {:?}", audio_path);
-            return Ok(audio_path.to_string_lossy().into_owned());
+            return audio_path.to_string_lossy().into_owned();
         }
     }
     
-    // extract audio from video
-    println!("Extracting audio from video");
-    let temp_audio_path = format!("temp_audio_{}.wav", std::process::id());
-    
-    extract_audio(video_path, &temp_audio_path)?;
-    
-    Ok(temp_audio_path)
+    video_path.to_string()
 }
-
-/// extract audio from video file using FFmpeg
-fn extract_audio(video_path: &str, output_path: &str) -> Result<()> {
-    use std::process::Command;
-    
-    let output = Command::new("ffmpeg")
-        .args(&[
-            "-i", video_path,
-            "-vn",
-            "-acodec", "pcm_s16le", 
-            "-ar", "44100",
-            "-f", "wav",
-            "-y",
-            output_path
-        ])
-        .output()
-        .map_err(|e| AurenaError::AudioExtractionError { 
-            err: Box::new(e) 
-        })?;
-    
-    if !output.status.success() {
-        let error = String::from_utf8_lossy(&output.stderr);
-        return Err(AurenaError::AudioExtractionError { 
-            err: Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other, 
-                format!("Audio extraction failed: {}", error)
-            )) 
-        });
-    }
-    
-    let metadata = std::fs::metadata(output_path)
-        .map_err(|e| AurenaError::IoError { err: e })?;
-    
-    if metadata.len() == 0 {
-        return Err(AurenaError::AudioExtractionError { 
-            err: Box::new(std::io::Error::new(
-                std::io::ErrorKind::InvalidData, 
-                "Empty audio file generated"
-            )) 
-        });
-    }
-    
-    Ok(())
-}
\ No newline at end of file
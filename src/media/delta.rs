@@ -0,0 +1,170 @@
+use image::RgbImage;
+
+/// channel difference below which a pixel counts as unchanged, absorbing codec noise
+const CHANGE_THRESHOLD: u8 = 12;
+
+/// frames between full redraws, which clear anything the deltas missed
+const FULL_REDRAW_INTERVAL: u32 = 300;
+
+/// past this share of changed rows, one full frame is cheaper than many strips
+const MAX_CHANGED_SHARE: f64 = 0.75;
+
+/// a horizontal strip of the frame, spanning whole text rows
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Region {
+    /// text row the strip starts on, counted from the top of the frame
+    pub row: u32,
+    pub y: u32,
+    pub height: u32,
+}
+
+/// tracks what is on screen so each frame only redraws the rows that changed
+///
+/// rows are compared a text row at a time, since that is as finely as the cursor
+/// can place an image
+pub struct FrameDiff {
+    /// the frame as drawn: rows are only updated when they are sent
+    reference: Option<RgbImage>,
+    row_height: u32,
+    frames_since_full: u32,
+}
+
+impl FrameDiff {
+    pub fn new(row_height: u32) -> Self {
+        FrameDiff { reference: None, row_height: row_height.max(1), frames_since_full: 0 }
+    }
+
+    /// forget the screen contents, so the next frame is drawn in full
+    pub fn reset(&mut self) {
+        self.reference = None;
+    }
+
+    /// the strips of `img` that differ from the screen; `None` means redraw the whole frame
+    pub fn changed_regions(&mut self, img: &RgbImage) -> Option<Vec<Region>> {
+        let reference = match self.reference.as_mut() {
+            Some(reference)
+                if reference.dimensions() == img.dimensions() && self.frames_since_full < FULL_REDRAW_INTERVAL =>
+            {
+                reference
+            }
+            _ => return self.redraw(img),
+        };
+
+        // compare raw bytes rather than pixels
+        let reference: &mut [u8] = reference;
+        let (width, height) = img.dimensions();
+        let row_bytes = width as usize * 3;
+        let mut regions: Vec<Region> = Vec::new();
+        let mut changed_rows = 0;
+
+        for (row, y) in (0..height).step_by(self.row_height as usize).enumerate() {
+            let rows_end = (y + self.row_height).min(height);
+            let bytes = y as usize * row_bytes..rows_end as usize * row_bytes;
+            let changed = img.as_raw()[bytes.clone()]
+                .iter()
+                .zip(&reference[bytes.clone()])
+                .any(|(a, b)| a.abs_diff(*b) > CHANGE_THRESHOLD);
+            if !changed {
+                continue;
+            }
+
+            reference[bytes.clone()].copy_from_slice(&img.as_raw()[bytes]);
+            changed_rows += rows_end - y;
+            match regions.last_mut() {
+                Some(last) if last.y + last.height == y => last.height += rows_end - y,
+                _ => regions.push(Region { row: row as u32, y, height: rows_end - y }),
+            }
+        }
+
+        if changed_rows as f64 > height as f64 * MAX_CHANGED_SHARE {
+            return self.redraw(img);
+        }
+        self.frames_since_full += 1;
+        Some(regions)
+    }
+
+    fn redraw(&mut self, img: &RgbImage) -> Option<Vec<Region>> {
+        self.reference = Some(img.clone());
+        self.frames_since_full = 0;
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    const ROW_HEIGHT: u32 = 10;
+
+    /// eight text rows of flat gray
+    fn frame() -> RgbImage {
+        RgbImage::from_pixel(16, ROW_HEIGHT * 8, Rgb([100, 100, 100]))
+    }
+
+    /// a copy of `img` with one pixel in each of `rows` shifted by `delta`
+    fn changed(img: &RgbImage, rows: &[u32], delta: u8) -> RgbImage {
+        let mut img = img.clone();
+        for row in rows {
+            img.put_pixel(3, row * ROW_HEIGHT + 4, Rgb([100 + delta, 100, 100]));
+        }
+        img
+    }
+
+    /// a diff that has already drawn `img` once
+    fn drawn(img: &RgbImage) -> FrameDiff {
+        let mut diff = FrameDiff::new(ROW_HEIGHT);
+        assert_eq!(diff.changed_regions(img), None);
+        diff
+    }
+
+    #[test]
+    fn adjacent_rows_merge_into_one_strip() {
+        let img = frame();
+        let mut diff = drawn(&img);
+        assert_eq!(diff.changed_regions(&img), Some(Vec::new()));
+
+        let regions = diff.changed_regions(&changed(&img, &[1, 2, 5], 50));
+        assert_eq!(
+            regions,
+            Some(vec![
+                Region { row: 1, y: 10, height: 20 },
+                Region { row: 5, y: 50, height: 10 },
+            ])
+        );
+    }
+
+    #[test]
+    fn noise_below_the_threshold_is_ignored() {
+        let img = frame();
+        let mut diff = drawn(&img);
+        assert_eq!(diff.changed_regions(&changed(&img, &[2], CHANGE_THRESHOLD)), Some(Vec::new()));
+        assert_eq!(
+            diff.changed_regions(&changed(&img, &[2], CHANGE_THRESHOLD + 1)),
+            Some(vec![Region { row: 2, y: 20, height: 10 }])
+        );
+    }
+
+    #[test]
+    fn a_new_size_is_drawn_in_full() {
+        let mut diff = drawn(&frame());
+        assert_eq!(diff.changed_regions(&RgbImage::new(16, ROW_HEIGHT * 4)), None);
+    }
+
+    #[test]
+    fn mostly_changed_frames_are_drawn_in_full() {
+        let img = frame();
+        let mut diff = drawn(&img);
+        // six of eight rows is exactly the limit, seven is past it
+        assert!(diff.changed_regions(&changed(&img, &[0, 1, 2, 3, 4, 5], 50)).is_some());
+        assert_eq!(diff.changed_regions(&changed(&img, &[0, 1, 2, 3, 4, 5, 6], 90)), None);
+    }
+
+    #[test]
+    fn reset_forces_a_full_redraw() {
+        let img = frame();
+        let mut diff = drawn(&img);
+        diff.reset();
+        assert_eq!(diff.changed_regions(&img), None);
+    }
+}
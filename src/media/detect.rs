@@ -0,0 +1,60 @@
+use crate::errors::{AurenaError, Result};
+use crate::media::animation::animation_format;
+use crate::media::input::MediaInput;
+use ffmpeg_next::{self as ffmpeg, format::stream::Disposition, media::Type};
+use image::ImageFormat;
+
+/// what a file holds, decided from its contents rather than its name
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MediaKind {
+    Image,
+    Animation,
+    Video,
+    Audio,
+}
+
+/// classify an input by its magic bytes, falling back to FFmpeg probing
+pub fn detect_media(input: &MediaInput) -> Result<MediaKind> {
+    let (path, header) = (input.path.as_str(), input.header.as_slice());
+
+    if animation_format(header).is_some() {
+        return Ok(MediaKind::Animation);
+    }
+    // TGA has no magic bytes, so it is the one format trusted by extension
+    let format = image::guess_format(header)
+        .ok()
+        .or_else(|| ImageFormat::from_path(path).ok().filter(|format| *format == ImageFormat::Tga));
+    // stills the image crate was built without are decoded through FFmpeg by handle_image
+    if format.is_some() {
+        return Ok(MediaKind::Image);
+    }
+
+    // probing would consume the stream, so anything else on stdin is handed to the demuxer
+    if input.is_stdin() {
+        return Ok(MediaKind::Video);
+    }
+
+    probe_streams(path).ok_or_else(|| AurenaError::UnsupportedMedia {
+        path: path.to_string(),
+    })
+}
+
+/// ask FFmpeg whether the file has a playable video or audio stream
+fn probe_streams(path: &str) -> Option<MediaKind> {
+    let ictx = ffmpeg::format::input(&path).ok()?;
+
+    // cover art in music files shows up as a single-picture video stream
+    let has_video = ictx.streams().any(|stream| {
+        stream.parameters().medium() == Type::Video
+            && !stream.disposition().contains(Disposition::ATTACHED_PIC)
+    });
+    let has_audio = ictx.streams().any(|stream| stream.parameters().medium() == Type::Audio);
+
+    if has_video {
+        Some(MediaKind::Video)
+    } else if has_audio {
+        Some(MediaKind::Audio)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,166 @@
+use crate::errors::{AurenaError, Result};
+use crate::SixelMode;
+use crate::terminal::{begin_synchronized_update, cursor_home, end_synchronized_update, save_cursor};
+use crate::sixel::color::{build_color_lookup_table, clip_palette};
+use crate::sixel::{SixelOptions, monochrome::monochrome_frame_convert, push_fmt};
+use crate::sixel::converter::{region_sixel_convert, video_sixel_convert};
+use crate::kitty::kitty_frame_convert;
+use crate::iterm::iterm_frame_convert;
+use crate::blocks::blocks_frame_convert;
+use crate::media::delta::Region;
+use crossterm::style::Color;
+use image::{DynamicImage, RgbImage, imageops};
+use std::io::Write;
+use std::ops::Range;
+use std::sync::{Arc, OnceLock};
+
+/// palette and lookup table for color mode
+type ColorPalette = (Vec<Color>, Vec<usize>);
+
+/// encodes a stream of already placed frames, reusing buffers between them
+pub struct FrameEncoder<'a> {
+    mode: SixelMode,
+    options: &'a SixelOptions,
+    // the palette is built from the first frame so adaptive palettes
+    // stay stable for the whole clip; workers share it
+    palette: Arc<OnceLock<ColorPalette>>,
+    band_buffers: Vec<Vec<u8>>,
+    /// size of the last encoded frame, so the next one rarely has to grow
+    frame_capacity: usize,
+    /// pixel size the buffers were sized for
+    dimensions: (u32, u32),
+}
+
+impl<'a> FrameEncoder<'a> {
+    pub fn new(mode: SixelMode, options: &'a SixelOptions, width: u32, height: u32) -> Self {
+        let frame_capacity = initial_capacity(&mode, width, height);
+        FrameEncoder {
+            mode,
+            options,
+            palette: Arc::new(OnceLock::new()),
+            band_buffers: Vec::new(),
+            frame_capacity,
+            dimensions: (width, height),
+        }
+    }
+
+    /// another encoder for the same clip, with its own buffers but the same palette
+    pub fn worker(&self) -> Self {
+        FrameEncoder {
+            mode: self.mode.clone(),
+            options: self.options,
+            palette: Arc::clone(&self.palette),
+            band_buffers: Vec::new(),
+            frame_capacity: self.frame_capacity,
+            dimensions: self.dimensions,
+        }
+    }
+
+    /// encode one frame for the output mode
+    pub fn encode(&mut self, rgb: RgbImage) -> Result<Vec<u8>> {
+        self.fit_buffers(rgb.dimensions());
+        let options = self.options;
+        let mut data = Vec::with_capacity(self.frame_capacity);
+        match self.mode {
+            SixelMode::Color => {
+                let (palette, color_lut) = self.palette.get_or_init(|| color_palette(&rgb, options));
+                video_sixel_convert(
+                    &DynamicImage::ImageRgb8(rgb),
+                    palette,
+                    color_lut,
+                    &mut self.band_buffers,
+                    options,
+                    &mut data,
+                )?
+            },
+            SixelMode::Monochrome => {
+                monochrome_frame_convert(&rgb, options, &mut data)?
+            },
+            SixelMode::Kitty => data = kitty_frame_convert(&rgb).into_bytes(),
+            SixelMode::Iterm => data = iterm_frame_convert(&rgb)?.into_bytes(),
+            SixelMode::Blocks => data = blocks_frame_convert(&rgb, true).into_bytes(),
+            SixelMode::Blocks256 => data = blocks_frame_convert(&rgb, false).into_bytes(),
+        }
+
+        self.frame_capacity = data.len();
+        Ok(data)
+    }
+
+    /// encode only the given strips of a color frame, as one image placed at the
+    /// text row of the first
+    pub fn encode_regions(&mut self, rgb: RgbImage, regions: &[Region]) -> Result<Vec<u8>> {
+        self.fit_buffers(rgb.dimensions());
+        let options = self.options;
+        let (palette, color_lut) = self.palette.get_or_init(|| color_palette(&rgb, options));
+
+        let mut data = Vec::new();
+        let (Some(first), Some(last)) = (regions.first(), regions.last()) else { return Ok(data) };
+        // unchanged rows between the strips are sent as transparent, which costs a
+        // few bytes per band instead of a palette per strip
+        let span = imageops::crop_imm(&rgb, 0, first.y, rgb.width(), last.y + last.height - first.y).to_image();
+        let strips: Vec<Range<u32>> = regions
+            .iter()
+            .map(|region| region.y - first.y..region.y - first.y + region.height)
+            .collect();
+
+        push_fmt(&mut data, format_args!("\x1b[{};1H", first.row + 1));
+        region_sixel_convert(
+            &DynamicImage::ImageRgb8(span),
+            &strips,
+            palette,
+            color_lut,
+            &mut self.band_buffers,
+            options,
+            &mut data,
+        )?;
+        Ok(data)
+    }
+
+    /// start over with fresh buffers when the frame size changes, so a terminal
+    /// that shrank does not keep buffers sized for the larger frames
+    fn fit_buffers(&mut self, dimensions: (u32, u32)) {
+        if dimensions != self.dimensions {
+            self.band_buffers = Vec::new();
+            self.frame_capacity = initial_capacity(&self.mode, dimensions.0, dimensions.1);
+            self.dimensions = dimensions;
+        }
+    }
+
+    /// draw an encoded frame over the previous one
+    pub fn present(&self, out: &mut impl Write, frame_data: &[u8]) -> Result<()> {
+        // every frame covers the whole image area, so overwriting it in place
+        // is enough; clearing first makes the picture blink. synchronized output
+        // keeps the terminal from showing it half drawn
+        begin_synchronized_update(out)
+            .and_then(|()| cursor_home(out))
+            .and_then(|()| out.write_all(frame_data))
+            // the cursor now sits below the picture, where the status line goes
+            .and_then(|()| save_cursor(out))
+            .and_then(|()| end_synchronized_update(out))
+            .and_then(|()| out.flush())
+            .map_err(|e| AurenaError::IoError { err: e })
+    }
+
+    /// draw strips from `encode_regions`, leaving the rest of the frame on screen
+    pub fn present_regions(&self, out: &mut impl Write, frame_data: &[u8]) -> Result<()> {
+        begin_synchronized_update(out)
+            .and_then(|()| out.write_all(frame_data))
+            .and_then(|()| end_synchronized_update(out))
+            .and_then(|()| out.flush())
+            .map_err(|e| AurenaError::IoError { err: e })
+    }
+}
+
+/// expected size of an encoded frame, before any has been encoded
+fn initial_capacity(mode: &SixelMode, width: u32, height: u32) -> usize {
+    match mode {
+        SixelMode::Color => (width * height / 2) as usize,
+        _ => 0,
+    }
+}
+
+fn color_palette(rgb: &RgbImage, options: &SixelOptions) -> ColorPalette {
+    let palette = clip_palette(rgb, options.palette, options.colors, options.metric);
+    let color_lut = build_color_lookup_table(&palette, options.metric);
+    (palette, color_lut)
+}
@@ -1,17 +1,101 @@
 use crate::errors::{AurenaError, Result};
 use crate::SixelMode;
-use crate::sixel::convert_image;
-use image::ImageReader;
+use crate::sixel::{convert_image, SixelOptions};
+use crate::media::input::MediaInput;
+use crate::media::video::decode_still;
+use image::metadata::Orientation;
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader, Rgb32FImage, RgbImage, Rgba, RgbaImage};
+use std::io::{self, Cursor, Write};
+
+/// Reinhard's "key": the brightness the average scene luminance is mapped to
+const TONE_MAP_KEY: f32 = 0.18;
 
 /// handle image file processing and display
-pub fn handle_image(path: &str, sixel_mode: SixelMode) -> Result<()> {
-    let img = ImageReader::open(path)
+pub fn handle_image(input: &MediaInput, sixel_mode: SixelMode, options: &SixelOptions) -> Result<()> {
+    let img = load_image(input)?;
+    let mut out = io::stdout().lock();
+    convert_image(&img, sixel_mode, options, &mut out)?;
+    
+    writeln!(out).and_then(|()| out.flush()).map_err(|e| AurenaError::IoError { err: e })
+}
+
+/// decode an image upright and at 8 bits per channel
+fn load_image(input: &MediaInput) -> Result<DynamicImage> {
+    // formats this build cannot read, such as AVIF, are recognized but decoded by FFmpeg
+    if image::guess_format(&input.header).is_ok_and(|format| !format.reading_enabled()) {
+        return decode_still(input);
+    }
+
+    let mut reader = ImageReader::new(Cursor::new(input.read_all()?));
+    // the extension only matters for formats without magic bytes
+    if let Ok(format) = ImageFormat::from_path(&input.path) {
+        reader.set_format(format);
+    }
+
+    let mut decoder = reader
+        .with_guessed_format()
         .map_err(|e| AurenaError::IoError { err: e })?
-        .decode()
+        .into_decoder()
         .map_err(|e| AurenaError::ImageLoadError { err: e })?;
-    
-    let sixel_data = convert_image(&img, sixel_mode)?;
-    
-    println!("{}", sixel_data);
-    Ok(())
-}
\ No newline at end of file
+
+    // cameras record rotation as an EXIF tag instead of rotating the pixels;
+    // a broken tag is not worth failing over
+    let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+    let mut img = DynamicImage::from_decoder(decoder)
+        .map_err(|e| AurenaError::ImageLoadError { err: e })?;
+    img.apply_orientation(orientation);
+
+    Ok(to_display_range(img))
+}
+
+/// bring high bit depth and floating point images down to 8 bits per channel
+fn to_display_range(img: DynamicImage) -> DynamicImage {
+    match img {
+        // float images (Radiance HDR, OpenEXR) hold linear light, often far above 1.0
+        DynamicImage::ImageRgb32F(ref hdr) => DynamicImage::ImageRgb8(tone_map(hdr)),
+        DynamicImage::ImageRgba32F(ref hdr) => {
+            let mapped = tone_map(&img.to_rgb32f());
+            let rgba = RgbaImage::from_fn(hdr.width(), hdr.height(), |x, y| {
+                let [r, g, b] = mapped.get_pixel(x, y).0;
+                let alpha = (hdr.get_pixel(x, y)[3].clamp(0.0, 1.0) * 255.0).round() as u8;
+                Rgba([r, g, b, alpha])
+            });
+            DynamicImage::ImageRgba8(rgba)
+        }
+        // 16-bit channels are rescaled with rounding rather than truncated
+        DynamicImage::ImageLuma16(_) | DynamicImage::ImageRgb16(_) => DynamicImage::ImageRgb8(img.to_rgb8()),
+        DynamicImage::ImageLumaA16(_) | DynamicImage::ImageRgba16(_) => DynamicImage::ImageRgba8(img.to_rgba8()),
+        img => img,
+    }
+}
+
+/// global Reinhard tone mapping, exposed for the scene's log-average luminance, then sRGB encoded
+fn tone_map(hdr: &Rgb32FImage) -> RgbImage {
+    let luminance = |[r, g, b]: [f32; 3]| 0.2126 * r + 0.7152 * g + 0.0722 * b;
+
+    let pixel_count = (hdr.width() as usize * hdr.height() as usize).max(1);
+    let log_sum: f64 = hdr
+        .pixels()
+        .map(|p| (1e-4 + luminance(p.0).max(0.0) as f64).ln())
+        .sum();
+    let log_average = (log_sum / pixel_count as f64).exp() as f32;
+    let exposure = TONE_MAP_KEY / log_average;
+
+    RgbImage::from_fn(hdr.width(), hdr.height(), |x, y| {
+        let rgb = hdr.get_pixel(x, y).0.map(|c| c.max(0.0) * exposure);
+        let lum = luminance(rgb);
+        // compress luminance only, keeping the hue of bright highlights
+        let scale = if lum > 0.0 { 1.0 / (1.0 + lum) } else { 0.0 };
+        image::Rgb(rgb.map(|c| (srgb_encode(c * scale) * 255.0).round() as u8))
+    })
+}
+
+/// linear light to sRGB transfer curve
+fn srgb_encode(linear: f32) -> f32 {
+    let linear = linear.clamp(0.0, 1.0);
+    if linear <= 0.003_130_8 {
+        linear * 12.92
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
+}
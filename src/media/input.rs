@@ -0,0 +1,210 @@
+use crate::errors::{AurenaError, Result};
+use ffmpeg_next::{self as ffmpeg, ffi, format::context::Input};
+use std::ffi::{c_int, c_void};
+use std::fs::File;
+use std::io::{self, Read};
+use std::mem::ManuallyDrop;
+use std::ops::{Deref, DerefMut};
+use std::ptr;
+
+/// input path meaning "read standard input"
+pub const STDIN_PATH: &str = "-";
+
+/// bytes read from the start of the input for magic-byte sniffing
+const SNIFF_BYTES: u64 = 64 * 1024;
+
+/// buffer FFmpeg reads streamed input through
+const AVIO_BUFFER_SIZE: usize = 64 * 1024;
+
+/// a media file, or standard input when the path is "-"
+pub struct MediaInput {
+    pub path: String,
+    /// first bytes of the input; for stdin they are replayed ahead of the rest of the stream
+    pub header: Vec<u8>,
+}
+
+impl MediaInput {
+    pub fn open(path: &str) -> Result<Self> {
+        let mut header = Vec::new();
+        let read = if path == STDIN_PATH {
+            io::stdin().lock().take(SNIFF_BYTES).read_to_end(&mut header)
+        } else {
+            File::open(path).and_then(|file| file.take(SNIFF_BYTES).read_to_end(&mut header))
+        };
+        read.map_err(|e| AurenaError::IoError { err: e })?;
+
+        Ok(MediaInput { path: path.to_string(), header })
+    }
+
+    pub fn is_stdin(&self) -> bool {
+        self.path == STDIN_PATH
+    }
+
+    /// load the whole input into memory, draining stdin
+    pub fn read_all(&self) -> Result<Vec<u8>> {
+        if self.is_stdin() {
+            let mut data = self.header.clone();
+            io::stdin()
+                .lock()
+                .read_to_end(&mut data)
+                .map_err(|e| AurenaError::IoError { err: e })?;
+            Ok(data)
+        } else {
+            std::fs::read(&self.path).map_err(|e| AurenaError::IoError { err: e })
+        }
+    }
+
+    /// open the input for demuxing; stdin is streamed through a custom AVIO reader
+    pub fn open_ffmpeg(&self) -> Result<FfmpegInput> {
+        if self.is_stdin() {
+            open_stream(StreamReader {
+                header: io::Cursor::new(self.header.clone()),
+                rest: io::stdin(),
+            })
+        } else {
+            let input = ffmpeg::format::input(&self.path)
+                .map_err(|_e| AurenaError::VideoOpenError { path: self.path.clone() })?;
+            Ok(FfmpegInput { input: ManuallyDrop::new(input), custom_io: None })
+        }
+    }
+}
+
+/// an FFmpeg demuxer, owning the custom I/O context when it reads from a stream
+pub struct FfmpegInput {
+    input: ManuallyDrop<Input>,
+    custom_io: Option<CustomIo>,
+}
+
+struct CustomIo {
+    avio: *mut ffi::AVIOContext,
+    reader: *mut StreamReader,
+}
+
+impl Deref for FfmpegInput {
+    type Target = Input;
+
+    fn deref(&self) -> &Input {
+        &self.input
+    }
+}
+
+impl DerefMut for FfmpegInput {
+    fn deref_mut(&mut self) -> &mut Input {
+        &mut self.input
+    }
+}
+
+impl Drop for FfmpegInput {
+    fn drop(&mut self) {
+        // the format context must be closed before the I/O context it reads from
+        // SAFETY: `input` is never used again after this point
+        unsafe { ManuallyDrop::drop(&mut self.input) };
+
+        if let Some(custom_io) = self.custom_io.take() {
+            // SAFETY: both pointers come from `open_stream` and nothing else references them now
+            unsafe {
+                let mut avio = custom_io.avio;
+                ffi::av_freep(ptr::addr_of_mut!((*avio).buffer).cast());
+                ffi::avio_context_free(&mut avio);
+                drop(Box::from_raw(custom_io.reader));
+            }
+        }
+    }
+}
+
+/// when the earliest stream of an input starts, in seconds
+///
+/// containers such as MPEG-TS start well after zero; playback counts from here,
+/// so it is subtracted from timestamps and added to seek targets
+pub fn start_offset(ictx: &Input) -> f64 {
+    // SAFETY: the format context stays valid for as long as `ictx` is borrowed
+    let start = unsafe { (*ictx.as_ptr()).start_time };
+    if start == ffi::AV_NOPTS_VALUE {
+        0.0
+    } else {
+        start as f64 / f64::from(ffi::AV_TIME_BASE)
+    }
+}
+
+/// the sniffed header followed by whatever is still unread on stdin
+struct StreamReader {
+    header: io::Cursor<Vec<u8>>,
+    rest: io::Stdin,
+}
+
+impl Read for StreamReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.header.read(buf)? {
+            0 => self.rest.read(buf),
+            n => Ok(n),
+        }
+    }
+}
+
+unsafe extern "C" fn read_packet(opaque: *mut c_void, buf: *mut u8, buf_size: c_int) -> c_int {
+    // SAFETY: FFmpeg passes back the reader registered in `open_stream`
+    // and a buffer valid for `buf_size` bytes
+    let (reader, buf) = unsafe {
+        (&mut *opaque.cast::<StreamReader>(), std::slice::from_raw_parts_mut(buf, buf_size as usize))
+    };
+
+    loop {
+        match reader.read(buf) {
+            Ok(0) => return ffi::AVERROR_EOF,
+            Ok(n) => return n as c_int,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => return ffi::AVERROR(libc::EIO),
+        }
+    }
+}
+
+/// open a non-seekable stream with FFmpeg, letting it probe the container format
+fn open_stream(reader: StreamReader) -> Result<FfmpegInput> {
+    let stream_error = |code: c_int| AurenaError::VideoStreamError { err: ffmpeg::Error::from(code) };
+
+    // SAFETY: every pointer handed to FFmpeg is either freshly allocated here or owned by
+    // the returned `FfmpegInput`, which releases them in the required order
+    unsafe {
+        let reader = Box::into_raw(Box::new(reader));
+        let buffer = ffi::av_malloc(AVIO_BUFFER_SIZE).cast::<u8>();
+        let mut avio = ffi::avio_alloc_context(
+            buffer,
+            AVIO_BUFFER_SIZE as c_int,
+            0,
+            reader.cast(),
+            Some(read_packet),
+            None,
+            None,
+        );
+
+        let free_io = |mut avio: *mut ffi::AVIOContext| {
+            ffi::av_freep(ptr::addr_of_mut!((*avio).buffer).cast());
+            ffi::avio_context_free(&mut avio);
+            drop(Box::from_raw(reader));
+        };
+
+        let mut context = ffi::avformat_alloc_context();
+        (*context).pb = avio;
+        (*context).flags |= ffi::AVFMT_FLAG_CUSTOM_IO as c_int;
+
+        // on failure FFmpeg frees the format context, but never a custom I/O context
+        let ret = ffi::avformat_open_input(&mut context, ptr::null(), ptr::null(), ptr::null_mut());
+        if ret < 0 {
+            free_io(avio);
+            return Err(stream_error(ret));
+        }
+
+        let ret = ffi::avformat_find_stream_info(context, ptr::null_mut());
+        if ret < 0 {
+            ffi::avformat_close_input(&mut context);
+            free_io(avio);
+            return Err(stream_error(ret));
+        }
+
+        avio = (*context).pb;
+        Ok(FfmpegInput {
+            input: ManuallyDrop::new(Input::wrap(context)),
+            custom_io: Some(CustomIo { avio, reader }),
+        })
+    }
+}
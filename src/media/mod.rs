@@ -1,3 +1,9 @@
 pub mod image;
+pub mod animation;
 pub mod video;
-pub mod audio;
\ No newline at end of file
+pub mod frame;
+pub mod audio;
+pub mod detect;
+pub mod input;
+pub mod playback;
+pub mod delta;
\ No newline at end of file
@@ -0,0 +1,285 @@
+use crate::errors::{AurenaError, Result};
+use crate::terminal::InterruptWatch;
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::terminal::{disable_raw_mode, enable_raw_mode};
+use std::time::{Duration, Instant};
+
+/// playback speeds stepped through with `[` and `]`
+const SPEEDS: [f64; 7] = [0.25, 0.5, 0.75, 1.0, 1.5, 2.0, 4.0];
+const NORMAL_SPEED: usize = 3;
+
+/// seek distances for the arrow keys, and with shift held
+const SEEK_SHORT: f64 = 5.0;
+const SEEK_LONG: f64 = 60.0;
+
+/// the part of a video to play
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PlaybackRange {
+    pub start: Duration,
+    pub end: Option<Duration>,
+}
+
+impl PlaybackRange {
+    /// build from --start and either --end or --duration
+    pub fn parse(start: Option<&str>, end: Option<&str>, duration: Option<&str>) -> Result<Self> {
+        let start = start.map(|s| parse_time("start", s)).transpose()?.unwrap_or_default();
+        let end = match (end, duration) {
+            (Some(end), _) => Some(parse_time("end", end)?),
+            (None, Some(duration)) => Some(start + parse_time("duration", duration)?),
+            (None, None) => None,
+        };
+
+        if let Some(end) = end.filter(|end| *end <= start) {
+            return Err(AurenaError::InvalidOption {
+                option: "end".to_string(),
+                value: format!("{:.3}s is not after the start time", end.as_secs_f64()),
+            });
+        }
+
+        Ok(PlaybackRange { start, end })
+    }
+
+    /// whether a frame at `position` is past the end of the range
+    pub fn is_past_end(&self, position: Duration) -> bool {
+        self.end.is_some_and(|end| position >= end)
+    }
+}
+
+/// parse "90", "90.5", "1:30" or "1:02:03.5" into a duration
+fn parse_time(option: &str, s: &str) -> Result<Duration> {
+    let invalid = || AurenaError::InvalidOption {
+        option: option.to_string(),
+        value: s.to_string(),
+    };
+
+    let parts: Vec<&str> = s.strip_suffix('s').unwrap_or(s).split(':').collect();
+    if parts.len() > 3 {
+        return Err(invalid());
+    }
+
+    let mut seconds = 0.0;
+    for (i, part) in parts.iter().enumerate() {
+        let value: f64 = part.parse().map_err(|_| invalid())?;
+        // only the last field may be fractional, and only the first may exceed 59
+        let is_last = i + 1 == parts.len();
+        if !value.is_finite() || value < 0.0 || (i > 0 && value >= 60.0) || (!is_last && value.fract() != 0.0) {
+            return Err(invalid());
+        }
+        seconds = seconds * 60.0 + value;
+    }
+
+    Ok(Duration::from_secs_f64(seconds))
+}
+
+/// a key press or terminal event during playback
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    TogglePause,
+    /// relative seek in seconds
+    Seek(f64),
+    Slower,
+    Faster,
+    /// show the next frame and stay paused
+    Step,
+    Quit,
+    /// Ctrl-C or SIGINT: quit, and exit with the interrupted status
+    Interrupt,
+    /// the terminal window changed size
+    Resize,
+}
+
+impl Command {
+    fn from_key(key: KeyEvent) -> Option<Self> {
+        match key.code {
+            KeyCode::Char(' ') => Some(Command::TogglePause),
+            KeyCode::Left if key.modifiers.contains(KeyModifiers::SHIFT) => Some(Command::Seek(-SEEK_LONG)),
+            KeyCode::Right if key.modifiers.contains(KeyModifiers::SHIFT) => Some(Command::Seek(SEEK_LONG)),
+            KeyCode::Left => Some(Command::Seek(-SEEK_SHORT)),
+            KeyCode::Right => Some(Command::Seek(SEEK_SHORT)),
+            KeyCode::Down | KeyCode::PageDown => Some(Command::Seek(-SEEK_LONG)),
+            KeyCode::Up | KeyCode::PageUp => Some(Command::Seek(SEEK_LONG)),
+            KeyCode::Char('[') => Some(Command::Slower),
+            KeyCode::Char(']') => Some(Command::Faster),
+            KeyCode::Char('.') => Some(Command::Step),
+            // raw mode turns Ctrl-C into a key press instead of SIGINT
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => Some(Command::Interrupt),
+            KeyCode::Char('q') | KeyCode::Esc => Some(Command::Quit),
+            _ => None,
+        }
+    }
+}
+
+/// keyboard input for playback; raw mode stays on while this is alive
+pub struct Controls {
+    enabled: bool,
+    interrupts: InterruptWatch,
+}
+
+impl Controls {
+    /// enable raw mode; without a terminal, playback simply runs without controls
+    pub fn new() -> Self {
+        Controls { enabled: enable_raw_mode().is_ok(), interrupts: InterruptWatch::start() }
+    }
+
+    /// whether SIGINT arrived, for loops that do not wait for keys
+    pub fn interrupted(&self) -> bool {
+        self.interrupts.interrupted()
+    }
+
+    /// wait up to `timeout` for a playback key or a resize
+    pub fn wait(&self, timeout: Duration) -> Option<Command> {
+        if !self.enabled {
+            std::thread::sleep(timeout);
+            return self.interrupted().then_some(Command::Interrupt);
+        }
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if self.interrupted() || !event::poll(remaining).unwrap_or(false) {
+                return self.interrupted().then_some(Command::Interrupt);
+            }
+            match event::read() {
+                // crossterm turns SIGWINCH into resize events
+                Ok(Event::Resize(_, _)) => return Some(Command::Resize),
+                Ok(Event::Key(key)) if key.kind != KeyEventKind::Release => {
+                    if let Some(command) = Command::from_key(key) {
+                        return Some(command);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Drop for Controls {
+    fn drop(&mut self) {
+        if self.enabled {
+            let _ = disable_raw_mode();
+        }
+    }
+}
+
+/// media time that follows pauses, seeks and speed changes
+pub struct PlaybackClock {
+    anchor: Instant,
+    anchor_position: Duration,
+    speed_index: usize,
+    paused: bool,
+}
+
+impl PlaybackClock {
+    pub fn new(position: Duration) -> Self {
+        PlaybackClock {
+            anchor: Instant::now(),
+            anchor_position: position,
+            speed_index: NORMAL_SPEED,
+            paused: false,
+        }
+    }
+
+    /// current media position
+    pub fn position(&self) -> Duration {
+        if self.paused {
+            self.anchor_position
+        } else {
+            self.anchor_position + self.anchor.elapsed().mul_f64(self.speed())
+        }
+    }
+
+    /// wall-clock time until the media position reaches `position`
+    pub fn until(&self, position: Duration) -> Duration {
+        position.saturating_sub(self.position()).div_f64(self.speed())
+    }
+
+    pub fn speed(&self) -> f64 {
+        SPEEDS[self.speed_index]
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.rebase();
+        self.paused = paused;
+    }
+
+    /// step the speed up or down, returning the new speed
+    pub fn change_speed(&mut self, faster: bool) -> f64 {
+        self.rebase();
+        self.speed_index = if faster {
+            (self.speed_index + 1).min(SPEEDS.len() - 1)
+        } else {
+            self.speed_index.saturating_sub(1)
+        };
+        self.speed()
+    }
+
+    pub fn seek(&mut self, position: Duration) {
+        self.anchor = Instant::now();
+        self.anchor_position = position;
+    }
+
+    fn rebase(&mut self) {
+        self.seek(self.position());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seconds(s: &str) -> Option<f64> {
+        parse_time("start", s).ok().map(|time| time.as_secs_f64())
+    }
+
+    #[test]
+    fn times_parse_in_every_form() {
+        assert_eq!(seconds("90"), Some(90.0));
+        assert_eq!(seconds("90.5"), Some(90.5));
+        assert_eq!(seconds("90s"), Some(90.0));
+        assert_eq!(seconds("1:30"), Some(90.0));
+        assert_eq!(seconds("1:02:03.5"), Some(3723.5));
+        // only the first field may exceed 59
+        assert_eq!(seconds("75:00"), Some(4500.0));
+    }
+
+    #[test]
+    fn malformed_times_are_rejected() {
+        for s in ["", "abc", "-5", "1:60", "1:02:60", "1.5:30", "1:2.5:03", "1:2:3:4", "inf", "NaN", "1::2"] {
+            assert_eq!(seconds(s), None, "{:?} was accepted", s);
+        }
+    }
+
+    #[test]
+    fn ranges_follow_end_or_duration() {
+        let range = PlaybackRange::parse(Some("10"), Some("1:00"), None).unwrap();
+        assert_eq!(range, PlaybackRange { start: Duration::from_secs(10), end: Some(Duration::from_secs(60)) });
+
+        let range = PlaybackRange::parse(Some("10"), None, Some("5")).unwrap();
+        assert_eq!(range.end, Some(Duration::from_secs(15)));
+
+        // --end wins over --duration
+        let range = PlaybackRange::parse(None, Some("20"), Some("5")).unwrap();
+        assert_eq!(range, PlaybackRange { start: Duration::ZERO, end: Some(Duration::from_secs(20)) });
+
+        assert_eq!(PlaybackRange::parse(None, None, None).unwrap(), PlaybackRange::default());
+    }
+
+    #[test]
+    fn ranges_must_end_after_they_start() {
+        assert!(PlaybackRange::parse(Some("30"), Some("30"), None).is_err());
+        assert!(PlaybackRange::parse(Some("30"), Some("10"), None).is_err());
+        assert!(PlaybackRange::parse(None, None, Some("0")).is_err());
+    }
+
+    #[test]
+    fn range_end_is_exclusive() {
+        let range = PlaybackRange::parse(None, Some("2"), None).unwrap();
+        assert!(!range.is_past_end(Duration::from_millis(1999)));
+        assert!(range.is_past_end(Duration::from_secs(2)));
+        assert!(!PlaybackRange::default().is_past_end(Duration::MAX));
+    }
+}
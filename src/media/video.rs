@@ -1,9 +1,13 @@
 use crate::errors::{AurenaError, Result};
 use crate::SixelMode;
-use crate::terminal::{get_terminal_size, clear_screen, flush_display};
-use crate::sixel::color::{get_palette, build_color_lookup_table};
-use crate::sixel::{converter::video_sixel_convert, monochrome::monochrome_sixel_convert};
+use crate::layout::{Placement, SizeOptions, terminal_placement};
+use crate::terminal::{PlaybackScreen, clear_screen, get_terminal_geometry, restore_cursor};
+use crate::sixel::SixelOptions;
 use crate::media::audio::audio_setup;
+use crate::media::delta::{FrameDiff, Region};
+use crate::media::frame::FrameEncoder;
+use crate::media::input::{FfmpegInput, MediaInput, start_offset};
+use crate::media::playback::{Command, Controls, PlaybackClock, PlaybackRange};
 use image::{DynamicImage, RgbImage};
 use ffmpeg_next::{
     self as ffmpeg,
@@ -12,18 +16,477 @@ use ffmpeg_next::{
         format::pixel::Pixel,
         frame::video::Video,
     },
+    format::context::Input,
+    Packet,
 };
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, BufWriter, Write};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
 use std::time::{Duration, Instant};
 
+/// how often keys are polled while paused or waiting on the decoder
+const PAUSED_POLL: Duration = Duration::from_millis(100);
+
+/// frame rate assumed when the stream does not declare one
+const FALLBACK_FPS: f64 = 30.0;
+
+/// the video clock follows the audio once they disagree by more than this
+const RESYNC_THRESHOLD: Duration = Duration::from_millis(40);
+
+/// late frames are skipped, but at least one in this many is still shown
+const MAX_CONSECUTIVE_DROPS: u32 = 8;
+
+const STATUS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// upper bound on encoder worker threads
+const MAX_ENCODERS: usize = 4;
+
+/// frames that may wait in each pipeline queue, per encoder
+const QUEUE_DEPTH: usize = 2;
+
+const OUTPUT_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// what the decoder thread reports once the stream is open
+struct StreamInfo {
+    placement: Placement,
+    frame_duration: Duration,
+    /// frames may be partial redraws, so none can be skipped once encoded
+    delta: bool,
+}
+
+/// playback settings and decoder state the writer works with
+struct WriterContext<'a> {
+    enable_audio: bool,
+    range: PlaybackRange,
+    info: StreamInfo,
+    /// clock position published for the decoder's lateness check
+    shown_position: &'a AtomicU64,
+    /// frames the decoder skipped, for the status line
+    decoder_drops: &'a AtomicU32,
+}
+
+/// sent from the writer to the decoder thread
+enum StreamRequest {
+    /// continue decoding at `position`, tagging frames with `generation`
+    Seek { position: Duration, generation: u32 },
+    /// the terminal changed size; scale frames for it from now on
+    Resize { generation: u32 },
+}
+
+/// a scaled frame on its way to an encoder
+struct FrameJob {
+    seq: u64,
+    generation: u32,
+    time: Duration,
+    image: RgbImage,
+    /// strips to redraw, or `None` for the whole frame
+    regions: Option<Vec<Region>>,
+}
+
+/// an encoded frame on its way to the writer
+struct EncodedFrame {
+    seq: u64,
+    generation: u32,
+    time: Duration,
+    data: Result<Vec<u8>>,
+    partial: bool,
+}
+
 /// handle video file processing and playback
-pub fn handle_video(path: &str, enable_audio: bool, sixel_mode: SixelMode) -> Result<()> {
+///
+/// frames flow through a pipeline: a decoder thread demuxes, decodes and scales them,
+/// encoder workers convert them in parallel, and this thread writes them out in order
+pub fn handle_video(
+    input: &MediaInput,
+    enable_audio: bool,
+    sixel_mode: SixelMode,
+    options: &SixelOptions,
+    range: PlaybackRange,
+) -> Result<()> {
     ffmpeg::init()
         .map_err(|e| AurenaError::VideoStreamError { err: e })?;
 
-    let mut ictx = ffmpeg::format::input(&path)
-        .map_err(|_e| AurenaError::VideoOpenError { 
-            path: path.to_string() 
-        })?;
+    // clock position in microseconds for the decoder's lateness check; 0 while paused
+    let shown_position = AtomicU64::new(0);
+    let decoder_drops = AtomicU32::new(0);
+
+    thread::scope(|scope| {
+        let encoders = thread::available_parallelism().map_or(1, |n| n.get()).clamp(1, MAX_ENCODERS);
+        let (ready_tx, ready_rx) = mpsc::channel();
+        let (jobs_tx, jobs_rx) = mpsc::sync_channel(encoders * QUEUE_DEPTH);
+        let (results_tx, results_rx) = mpsc::sync_channel(encoders * QUEUE_DEPTH);
+        let (requests_tx, requests_rx) = mpsc::channel();
+
+        let decoder = {
+            let (sixel_mode, shown_position, decoder_drops) = (sixel_mode.clone(), &shown_position, &decoder_drops);
+            scope.spawn(move || {
+                let stream = match open_stream(input, sixel_mode, options) {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        let _ = ready_tx.send(Err(e));
+                        return Ok(());
+                    }
+                };
+                let _ = ready_tx.send(Ok(StreamInfo {
+                    placement: stream.placement,
+                    frame_duration: stream.frame_duration,
+                    delta: stream.diff.is_some(),
+                }));
+                stream.run(range, jobs_tx, requests_rx, shown_position, decoder_drops)
+            })
+        };
+
+        let info = ready_rx.recv().map_err(|_| AurenaError::ProcessingError {
+            msg: "Video decoder stopped unexpectedly".to_string(),
+        })??;
+        let placement = info.placement;
+
+        let encoder = FrameEncoder::new(sixel_mode, options, placement.width, placement.height);
+        // workers share one queue; it closes once the last of them exits
+        let jobs_rx = Arc::new(Mutex::new(jobs_rx));
+        for _ in 0..encoders {
+            let (mut worker, jobs_rx, results_tx) = (encoder.worker(), Arc::clone(&jobs_rx), results_tx.clone());
+            scope.spawn(move || loop {
+                let job = jobs_rx.lock().ok().and_then(|jobs| jobs.recv().ok());
+                let Some(job) = job else { break };
+                let frame = EncodedFrame {
+                    seq: job.seq,
+                    generation: job.generation,
+                    time: job.time,
+                    partial: job.regions.is_some(),
+                    data: match job.regions {
+                        Some(regions) => worker.encode_regions(job.image, &regions),
+                        None => worker.encode(job.image),
+                    },
+                };
+                if results_tx.send(frame).is_err() {
+                    break;
+                }
+            });
+        }
+        drop((jobs_rx, results_tx));
+
+        let context = WriterContext {
+            enable_audio,
+            range,
+            info,
+            shown_position: &shown_position,
+            decoder_drops: &decoder_drops,
+        };
+        write_frames(input, &encoder, context, results_rx, requests_tx)?;
+
+        // with the writer gone the decoder stops at its next frame; joining it here
+        // reports its error or panic instead of the scope panicking again
+        decoder.join().map_err(|_| AurenaError::ProcessingError {
+            msg: "Video decoder panicked".to_string(),
+        })?
+    })
+}
+
+/// present encoded frames in order and on schedule, handling keys and audio
+fn write_frames(
+    input: &MediaInput,
+    encoder: &FrameEncoder,
+    context: WriterContext,
+    results: Receiver<EncodedFrame>,
+    requests: Sender<StreamRequest>,
+) -> Result<()> {
+    let WriterContext { enable_audio, range, info, shown_position, decoder_drops } = context;
+    let mut audio = if enable_audio && input.is_stdin() {
+        eprintln!("Audio is not available when reading from stdin. Continuing without audio.");
+        None
+    } else if enable_audio {
+        match audio_setup(&input.path) {
+            Ok(player) => Some(player),
+            Err(e) => {
+                eprintln!("Audio setup failed: {}. Continuing without audio.", e);
+                None
+            }
+        }
+    } else {
+        println!("Audio disabled");
+        None
+    };
+
+    if !range.start.is_zero()
+        && let Some(audio) = audio.as_mut()
+    {
+        audio.seek(range.start)?;
+    }
+
+    let controls = Controls::new();
+    // declared before the writer, so buffered output is flushed before the screen is restored
+    let _screen = PlaybackScreen::enter().map_err(|e| AurenaError::IoError { err: e })?;
+    // frames are large; write them through one buffer instead of many small stdout writes
+    let mut out = BufWriter::with_capacity(OUTPUT_BUFFER_SIZE, io::stdout().lock());
+    let mut clock = PlaybackClock::new(range.start);
+    let publish = |clock: &PlaybackClock| {
+        let position = if clock.is_paused() { 0 } else { clock.position().as_micros() as u64 };
+        shown_position.store(position, Ordering::Relaxed);
+    };
+    // frames are numbered by the decoder and may finish encoding out of order
+    let mut pending: BTreeMap<u64, EncodedFrame> = BTreeMap::new();
+    let mut next_seq = 0;
+    // bumped on every seek; frames from before it are discarded
+    let mut generation = 0;
+    // stepping moves the picture but not the audio; resync on resume
+    let mut audio_stale = false;
+    let mut quit = false;
+    let mut interrupted = false;
+    // keys read while waiting on the decoder, handled once a frame is due
+    let mut deferred: VecDeque<Command> = VecDeque::new();
+    // after a resize the old, possibly larger picture has to go
+    let mut clear_pending = false;
+
+    let mut frame_count: u32 = 0;
+    let mut dropped_frames: u32 = 0;
+    let mut consecutive_drops = 0;
+    // video timestamp minus audio position when the last frame was shown
+    let mut drift: Option<f64> = None;
+    let start_time = Instant::now();
+    let mut last_status = start_time;
+    
+    // start audio playback
+    if let Some(ref audio) = audio {
+        audio.sink().play();
+    }
+
+    'playback: loop {
+        publish(&clock);
+        if controls.interrupted() {
+            interrupted = true;
+            break;
+        }
+        let frame = match pending.remove(&next_seq) {
+            Some(frame) => frame,
+            None => match results.recv_timeout(PAUSED_POLL) {
+                Ok(frame) => {
+                    pending.insert(frame.seq, frame);
+                    continue;
+                }
+                // the decoder is stalled, on a slow pipe say; keys still have to work
+                Err(RecvTimeoutError::Timeout) => match controls.wait(Duration::ZERO) {
+                    Some(Command::Quit) => {
+                        quit = true;
+                        break;
+                    }
+                    Some(Command::Interrupt) => {
+                        interrupted = true;
+                        break;
+                    }
+                    Some(command) => {
+                        deferred.push_back(command);
+                        continue;
+                    }
+                    None => continue,
+                },
+                // the decoder finished and every worker has drained
+                Err(RecvTimeoutError::Disconnected) => break,
+            },
+        };
+        next_seq += 1;
+        if frame.generation != generation {
+            continue;
+        }
+        let (frame_time, frame_data) = (frame.time, frame.data?);
+        let frame_duration = info.frame_duration;
+
+        // the audio is the master clock while it plays
+        let audio_position = audio
+            .as_ref()
+            .filter(|_| !audio_stale && !clock.is_paused())
+            .map(|audio| audio.position());
+        if let Some(position) = audio_position
+            && clock.position().abs_diff(position) > RESYNC_THRESHOLD
+        {
+            clock.seek(position);
+        }
+
+        // wait until the frame is due, handling keys in the meantime; keys are read
+        // at least once per frame, so they keep working while playback runs late
+        loop {
+            let wait = if clock.is_paused() { PAUSED_POLL } else { clock.until(frame_time) };
+
+            match deferred.pop_front().or_else(|| controls.wait(wait)) {
+                None if wait.is_zero() => break,
+                None => {}
+                Some(Command::TogglePause) => {
+                    let paused = !clock.is_paused();
+                    clock.set_paused(paused);
+                    if let Some(audio) = audio.as_mut() {
+                        if !paused && audio_stale {
+                            if let Err(e) = audio.seek(clock.position()) {
+                                eprintln!("Audio seek failed: {}", e);
+                            }
+                            audio_stale = false;
+                        }
+                        if paused { audio.sink().pause() } else { audio.sink().play() }
+                    }
+                }
+                Some(Command::Step) => {
+                    if !clock.is_paused() {
+                        clock.set_paused(true);
+                        if let Some(audio) = audio.as_ref() {
+                            audio.sink().pause();
+                        }
+                    }
+                    audio_stale = audio.is_some();
+                    break;
+                }
+                Some(command @ (Command::Slower | Command::Faster)) => {
+                    let speed = clock.change_speed(command == Command::Faster);
+                    if let Some(audio) = audio.as_ref() {
+                        audio.sink().set_speed(speed as f32);
+                    }
+                }
+                Some(Command::Seek(delta)) => {
+                    let target = (clock.position().as_secs_f64() + delta).max(0.0);
+                    let position = Duration::from_secs_f64(target);
+                    generation += 1;
+                    if requests.send(StreamRequest::Seek { position, generation }).is_err() {
+                        // the decoder already reached the end; nothing left to seek in
+                        break 'playback;
+                    }
+
+                    clock.seek(position);
+                    if let Some(audio) = audio.as_mut() {
+                        if let Err(e) = audio.seek(position) {
+                            eprintln!("Audio seek failed: {}", e);
+                        }
+                        audio_stale = false;
+                    }
+                    continue 'playback;
+                }
+                Some(Command::Quit) => {
+                    quit = true;
+                    break 'playback;
+                }
+                Some(Command::Interrupt) => {
+                    interrupted = true;
+                    break 'playback;
+                }
+                Some(Command::Resize) => {
+                    clear_pending = true;
+                    // queued frames have the old size; the decoder rescales from the next one on,
+                    // and the audio carries on undisturbed
+                    if requests.send(StreamRequest::Resize { generation: generation + 1 }).is_ok() {
+                        generation += 1;
+                        continue 'playback;
+                    }
+                }
+            }
+            publish(&clock);
+        }
+
+        // frames that fell behind while queued are skipped rather than shown late,
+        // unless later frames only redraw what changed since this one
+        if !info.delta
+            && !clock.is_paused()
+            && clock.position() > frame_time + frame_duration
+            && consecutive_drops < MAX_CONSECUTIVE_DROPS
+        {
+            dropped_frames += 1;
+            consecutive_drops += 1;
+            continue;
+        }
+        consecutive_drops = 0;
+
+        if clear_pending {
+            clear_screen(&mut out).map_err(|e| AurenaError::IoError { err: e })?;
+            clear_pending = false;
+        }
+        if frame.partial {
+            encoder.present_regions(&mut out, &frame_data)?;
+        } else {
+            encoder.present(&mut out, &frame_data)?;
+        }
+        frame_count += 1;
+
+        if clock.is_paused() {
+            // a stepped frame moves the paused position along with it
+            clock.seek(frame_time);
+        }
+        if let Some(audio) = audio.as_ref().filter(|_| !audio_stale && !clock.is_paused()) {
+            drift = Some(frame_time.as_secs_f64() - audio.position().as_secs_f64());
+        }
+        
+        if last_status.elapsed() >= STATUS_INTERVAL {
+            last_status = Instant::now();
+            let elapsed = start_time.elapsed();
+            let actual_fps = frame_count as f64 / elapsed.as_secs_f64();
+            let video_time = clock.position().as_secs();
+            let dropped = dropped_frames + decoder_drops.load(Ordering::Relaxed);
+            let speed = match clock.speed() {
+                1.0 => String::new(),
+                speed => format!(" | Speed: {}x", speed),
+            };
+            
+            let status = if let Some(ref audio) = audio {
+                let is_paused = audio.sink().is_paused();
+                let drift = drift.map_or(String::new(), |d| format!(" | A/V: {:+.0}ms", d * 1000.0));
+                format!("{}:{:02} | Frame: {} | Dropped: {} | FPS: {:.1}{}{} | Audio: {}", 
+                        video_time / 60, video_time % 60,
+                        frame_count, dropped, actual_fps, speed, drift,
+                        if is_paused { "PAUSED" } else { "PLAYING" })
+            } else {
+                format!("{}:{:02} | Frame: {} | Dropped: {} | FPS: {:.1}{}", 
+                        video_time / 60, video_time % 60,
+                        frame_count, dropped, actual_fps, speed)
+            };
+            // rewrite the line under the picture; a newline there would scroll the frame.
+            // partial redraws leave the cursor inside the picture, so go back below it
+            restore_cursor(&mut out)
+                .and_then(|()| write!(out, "\r{}\x1b[K", status))
+                .and_then(|()| out.flush())
+                .map_err(|e| AurenaError::IoError { err: e })?;
+        }
+    }
+
+    // dropping the audio player stops the sink, and the screen guard restores the terminal
+    if interrupted {
+        return Err(AurenaError::Interrupted);
+    }
+
+    // the range ended, or the user quit: stop the audio with the picture
+    if range.end.is_some() {
+        quit = true;
+    }
+
+    // let the audio tail finish, unless the user quit
+    if let Some(audio) = audio.filter(|_| !quit) {
+        let audio_wait_start = Instant::now();
+        let max_wait = Duration::from_secs(5);
+        
+        while !audio.sink().empty() && audio_wait_start.elapsed() < max_wait {
+            std::thread::sleep(Duration::from_millis(100));
+        }
+    }
+    
+    Ok(())
+}
+
+/// the demuxer, decoder and scaler of a video stream
+struct VideoStream {
+    ictx: FfmpegInput,
+    decoder: ffmpeg::decoder::Video,
+    scaler: Scaler,
+    video_stream_index: usize,
+    time_base: f64,
+    /// start time of the input, subtracted from frame timestamps
+    start_offset: f64,
+    placement: Placement,
+    frame_duration: Duration,
+    /// set when only changed rows of color sixel frames are redrawn
+    diff: Option<FrameDiff>,
+    sixel_mode: SixelMode,
+    size: SizeOptions,
+}
+
+fn open_stream(input: &MediaInput, sixel_mode: SixelMode, options: &SixelOptions) -> Result<VideoStream> {
+    let ictx = input.open_ffmpeg()?;
     
     let input_stream = ictx
         .streams()
@@ -35,160 +498,237 @@ pub fn handle_video(path: &str, enable_audio: bool, sixel_mode: SixelMode) -> Re
     let video_stream_index = input_stream.index();
     let context_decoder = ffmpeg::codec::context::Context::from_parameters(input_stream.parameters())
         .map_err(|e| AurenaError::VideoStreamError { err: e })?;
-    let mut decoder = context_decoder.decoder().video()
+    let decoder = context_decoder.decoder().video()
         .map_err(|e| AurenaError::VideoStreamError { err: e })?;
 
-    let (term_w, term_h) = get_terminal_size()
-        .ok_or(AurenaError::TerminalSizeError)?;
-    
-    let scale_factor = f64::min(
-        term_w as f64 / decoder.width() as f64,
-        term_h as f64 / decoder.height() as f64
-    ).min(1.0);
-    
-    let target_width = (decoder.width() as f64 * scale_factor) as u32;
-    let target_height = (decoder.height() as f64 * scale_factor) as u32;
+    let placement = terminal_placement(&sixel_mode, decoder.width(), decoder.height(), &options.size)?;
+    let scaler = frame_scaler(&decoder, &placement)?;
+
+    // frames are scheduled by their timestamps; the rate only fills in missing ones
+    let fps = f64::from(input_stream.avg_frame_rate());
+    let fps = if fps.is_finite() && fps > 0.0 { fps } else { FALLBACK_FPS };
+    let frame_duration = Duration::from_secs_f64(1.0 / fps);
+    let time_base = f64::from(input_stream.time_base());
+    let start_offset = start_offset(&ictx);
+
+    // strips are placed by text row, so the cell height has to be known
+    let diff = (sixel_mode == SixelMode::Color && options.delta)
+        .then(get_terminal_geometry)
+        .flatten()
+        .map(|geometry| FrameDiff::new(geometry.cell_height));
+
+    Ok(VideoStream {
+        ictx,
+        decoder,
+        scaler,
+        video_stream_index,
+        time_base,
+        start_offset,
+        placement,
+        frame_duration,
+        diff,
+        sixel_mode,
+        size: options.size.clone(),
+    })
+}
+
+/// decode the first picture of an input at its native size, for still formats
+/// the image crate was built without
+pub fn decode_still(input: &MediaInput) -> Result<DynamicImage> {
+    let mut ictx = input.open_ffmpeg()?;
+    let input_stream = ictx
+        .streams()
+        .best(ffmpeg::media::Type::Video)
+        .ok_or(AurenaError::VideoDecodingError {
+            msg: "No picture found".to_string()
+        })?;
+    let stream_index = input_stream.index();
+    let mut decoder = ffmpeg::codec::context::Context::from_parameters(input_stream.parameters())
+        .and_then(|context| context.decoder().video())
+        .map_err(|e| AurenaError::VideoStreamError { err: e })?;
 
-    let mut scaler = Scaler::get(
+    let (width, height) = (decoder.width(), decoder.height());
+    let placement = Placement { scaled_width: width, scaled_height: height, crop_x: 0, crop_y: 0, width, height };
+    let mut scaler = frame_scaler(&decoder, &placement)?;
+
+    let mut frame = Video::empty();
+    let mut flushed = false;
+    while decoder.receive_frame(&mut frame).is_err() {
+        if flushed {
+            return Err(AurenaError::VideoDecodingError { msg: "No picture could be decoded".to_string() });
+        }
+        let mut packet = Packet::empty();
+        let sent = match packet.read(&mut ictx) {
+            Ok(()) if packet.stream() != stream_index => continue,
+            Ok(()) => decoder.send_packet(&packet),
+            // a lone picture may only come out once the decoder is drained
+            Err(ffmpeg::Error::Eof) => {
+                flushed = true;
+                decoder.send_eof()
+            }
+            Err(_) => continue,
+        };
+        sent.map_err(|e| AurenaError::VideoDecodingError { msg: format!("Failed to send packet: {}", e) })?;
+    }
+
+    let mut rgb_frame = Video::empty();
+    scaler.run(&frame, &mut rgb_frame)
+        .map_err(|e| AurenaError::VideoStreamError { err: e })?;
+    Ok(DynamicImage::ImageRgb8(frame_to_image(&rgb_frame, &placement)))
+}
+
+/// scaler from decoded frames to the placement's scaled size
+fn frame_scaler(decoder: &ffmpeg::decoder::Video, placement: &Placement) -> Result<Scaler> {
+    Scaler::get(
         decoder.format(),
         decoder.width(),
         decoder.height(),
         Pixel::RGB24,
-        target_width,
-        target_height,
+        placement.scaled_width,
+        placement.scaled_height,
         Flags::FAST_BILINEAR,
-    ).map_err(|e| AurenaError::VideoStreamError { err: e })?;
-
-    let fps = input_stream.avg_frame_rate().0 as f64 / input_stream.avg_frame_rate().1 as f64;
-    let frame_duration = Duration::from_secs_f64(1.0 / fps.max(30.0));
+    ).map_err(|e| AurenaError::VideoStreamError { err: e })
+}
 
-    // audio setup only for color mode 
-    let (audio_sink, _audio_stream) = if enable_audio {
-        match audio_setup(path) {
-            Ok((sink, stream)) => (Some(sink), Some(stream)),
-            Err(e) => {
-                eprintln!("Audio setup failed: {}. Continuing without audio.", e);
-                (None, None)
-            }
+impl VideoStream {
+    /// fit frames to the terminal again after it was resized
+    fn rescale(&mut self) -> Result<()> {
+        let placement = terminal_placement(&self.sixel_mode, self.decoder.width(), self.decoder.height(), &self.size)?;
+        self.scaler = frame_scaler(&self.decoder, &placement)?;
+        self.placement = placement;
+        // the screen is cleared, and the cell height may have changed with the font
+        if self.diff.is_some() {
+            self.diff = get_terminal_geometry().map(|geometry| FrameDiff::new(geometry.cell_height));
         }
-    } else {
-        println!("Audio disabled");
-        (None, None)
-    };
+        Ok(())
+    }
 
-    // setup conversion buffers based on mode
-    let (palette, color_lut, mut sixel_buffer, mut color_bands) = match sixel_mode {
-        SixelMode::Color => {
-            let palette = get_palette();
-            let color_lut = build_color_lookup_table(&palette);
-            let sixel_buffer = Vec::with_capacity((target_width * target_height / 2) as usize);
-            let color_bands: Vec<Vec<u8>> = vec![Vec::with_capacity(target_width as usize); palette.len()];
-            (Some(palette), Some(color_lut), sixel_buffer, Some(color_bands))
-        },
-        SixelMode::Monochrome => {
-            (None, None, Vec::new(), None)
+    /// decode and scale frames into the job queue until the end of the range,
+    /// or until nothing is listening anymore
+    fn run(
+        mut self,
+        range: PlaybackRange,
+        jobs: SyncSender<FrameJob>,
+        requests: Receiver<StreamRequest>,
+        shown_position: &AtomicU64,
+        drops: &AtomicU32,
+    ) -> Result<()> {
+        // after a seek, frames before the target are decoded but not shown
+        let mut seek_target: Option<f64> = None;
+        if !range.start.is_zero() {
+            // streams cannot seek, but decoding from the top and skipping to the target still works
+            let _ = seek_input(&mut self.ictx, range.start);
+            seek_target = Some(range.start.as_secs_f64());
         }
-    };
 
-    let mut frame_count = 0;
-    let start_time = Instant::now();
-    
-    // start audio playback
-    if let Some(ref sink) = audio_sink {
-        sink.play();
-    }
+        // expected time of the next frame, for frames without a timestamp
+        let mut frame_position = range.start;
+        let mut generation = 0;
+        let mut seq = 0;
+        let mut consecutive_drops = 0;
+
+        loop {
+            while let Ok(request) = requests.try_recv() {
+                match request {
+                    StreamRequest::Seek { position, generation: next_generation } => {
+                        generation = next_generation;
+                        if seek_input(&mut self.ictx, position).is_ok() {
+                            self.decoder.flush();
+                            seek_target = Some(position.as_secs_f64());
+                            frame_position = position;
+                        }
+                    }
+                    StreamRequest::Resize { generation: next_generation } => {
+                        generation = next_generation;
+                        // a terminal that cannot be measured right now keeps the old size
+                        let _ = self.rescale();
+                    }
+                }
+                // the writer discards frames from before the request, so rows the diff
+                // counts as drawn may never reach the screen
+                if let Some(diff) = self.diff.as_mut() {
+                    diff.reset();
+                }
+            }
+
+            let mut packet = Packet::empty();
+            match packet.read(&mut self.ictx) {
+                Ok(()) => {}
+                Err(ffmpeg::Error::Eof) => return Ok(()),
+                Err(_) => continue,
+            }
+            if packet.stream() != self.video_stream_index {
+                continue;
+            }
 
-    for (stream, packet) in ictx.packets() {
-        if stream.index() == video_stream_index {
-            decoder.send_packet(&packet)
+            self.decoder.send_packet(&packet)
                 .map_err(|e| AurenaError::VideoDecodingError { 
                     msg: format!("Failed to send packet: {}", e) 
                 })?;
             
             let mut frame = Video::empty();
 
-            while decoder.receive_frame(&mut frame).is_ok() {
+            while self.decoder.receive_frame(&mut frame).is_ok() {
+                let timestamp = frame.timestamp().map(|ts| ts as f64 * self.time_base - self.start_offset);
+                if let Some(target) = seek_target {
+                    if timestamp.is_some_and(|t| t < target) {
+                        continue;
+                    }
+                    seek_target = None;
+                }
+
+                let frame_time = timestamp.map_or(frame_position, |t| Duration::from_secs_f64(t.max(0.0)));
+                frame_position = frame_time + self.frame_duration;
+                if range.is_past_end(frame_time) {
+                    return Ok(());
+                }
+
+                // a frame whose successor is already due is not worth scaling and encoding
+                let shown = Duration::from_micros(shown_position.load(Ordering::Relaxed));
+                if shown > frame_position && consecutive_drops < MAX_CONSECUTIVE_DROPS {
+                    drops.fetch_add(1, Ordering::Relaxed);
+                    consecutive_drops += 1;
+                    continue;
+                }
+                consecutive_drops = 0;
+
                 let mut rgb_frame = Video::empty();
-                scaler.run(&frame, &mut rgb_frame)
+                self.scaler.run(&frame, &mut rgb_frame)
                     .map_err(|e| AurenaError::VideoStreamError { err: e })?;
 
-                let rgb = frame_to_image(&rgb_frame);
-                let img = DynamicImage::ImageRgb8(rgb);
-
-                let sixel_data = match sixel_mode {
-                    SixelMode::Color => {
-                        video_sixel_convert(
-                            &img, 
-                            palette.as_ref().unwrap(),
-                            color_lut.as_ref().unwrap(),
-                            &mut sixel_buffer,
-                            color_bands.as_mut().unwrap()
-                        )?
-                    },
-                    SixelMode::Monochrome => {
-                        monochrome_sixel_convert(&img)?
-                    }
+                let image = frame_to_image(&rgb_frame, &self.placement);
+                let job = FrameJob {
+                    seq,
+                    generation,
+                    time: frame_time,
+                    regions: self.diff.as_mut().and_then(|diff| diff.changed_regions(&image)),
+                    image,
                 };
-                
-                clear_screen();
-                print!("{}", sixel_data);
-                flush_display().map_err(|e| AurenaError::IoError { err: e })?;
-
-                frame_count += 1;
-                
-                let target_time = start_time + frame_duration * frame_count;
-                let current_time = Instant::now();
-                
-                if target_time > current_time {
-                    let sleep_duration = target_time - current_time;
-                    if sleep_duration < Duration::from_millis(100) {
-                        std::thread::sleep(sleep_duration);
-                    }
-                }
-                
-                if frame_count % (fps as u32 * 5) == 0 {
-                    let elapsed = start_time.elapsed();
-                    let actual_fps = frame_count as f64 / elapsed.as_secs_f64();
-                    let video_time = elapsed.as_secs();
-                    
-                    if let Some(ref sink) = audio_sink {
-                        let is_paused = sink.is_paused();
-                        println!("\r{}:{:02} | Frame: {} | FPS: {:.1} | Audio: {}", 
-                                video_time / 60, video_time % 60,
-                                frame_count, actual_fps,
-                                if is_paused { "PAUSED" } else { "PLAYING" });
-                    } else {
-                        println!("\r{}:{:02} | Frame: {} | FPS: {:.1}", 
-                                video_time / 60, video_time % 60,
-                                frame_count, actual_fps);
-                    }
+                seq += 1;
+                if jobs.send(job).is_err() {
+                    return Ok(());
                 }
             }
         }
     }
+}
 
-    // audio cleanup
-    if let Some(sink) = audio_sink {
-        let audio_wait_start = Instant::now();
-        let max_wait = Duration::from_secs(5);
-        
-        while !sink.empty() && audio_wait_start.elapsed() < max_wait {
-            std::thread::sleep(Duration::from_millis(100));
-        }
-    }
-    
-    Ok(())
+/// seek to the keyframe at or before `position`; frames up to it are then decoded and skipped
+fn seek_input(ictx: &mut Input, position: Duration) -> Result<()> {
+    let timestamp = ((position.as_secs_f64() + start_offset(ictx)) * f64::from(ffmpeg::ffi::AV_TIME_BASE)) as i64;
+    ictx.seek(timestamp, ..timestamp)
+        .map_err(|e| AurenaError::VideoStreamError { err: e })
 }
 
-/// convert FFmpeg video frame to RgbImage
-fn frame_to_image(frame: &Video) -> RgbImage {
-    let mut img = RgbImage::new(frame.width(), frame.height());
+/// convert FFmpeg video frame to RgbImage, cropping it to the placement
+fn frame_to_image(frame: &Video, placement: &Placement) -> RgbImage {
+    let mut img = RgbImage::new(placement.width, placement.height);
     let data = frame.data(0);
     let stride = frame.stride(0) as usize;
 
     for (y, row) in img.enumerate_rows_mut() {
         for (x, _, pixel) in row {
-            let offset = y as usize * stride + x as usize * 3;
+            let offset = (y + placement.crop_y) as usize * stride + (x + placement.crop_x) as usize * 3;
             *pixel = image::Rgb([data[offset], data[offset + 1], data[offset + 2]]);
         }
     }
@@ -1,5 +1,77 @@
+use crate::errors::{AurenaError, Result};
+use crate::sixel::metric::ColorMetric;
+use crate::sixel::quantize::{median_cut_palette, octree_palette};
 use crossterm::style::Color;
-use image::Rgb;
+use image::{Rgb, RgbImage};
+use rayon::prelude::*;
+
+/// bits per channel kept by the color lookup table (32x32x32 = 32768 entries)
+const LUT_BITS: usize = 5;
+const LUT_QUANT: usize = 1 << LUT_BITS;
+const LUT_STEP: usize = 256 / LUT_QUANT;
+
+/// largest palette a sixel terminal is expected to hold
+pub const MAX_PALETTE_COLORS: usize = 256;
+
+/// how the sixel palette is chosen
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PaletteKind {
+    /// hand-picked palette from `get_palette`
+    Fixed,
+    /// adaptive palette built with median cut
+    MedianCut,
+    /// adaptive palette built with octree reduction
+    Octree,
+}
+
+impl PaletteKind {
+    pub fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "fixed" => Ok(PaletteKind::Fixed),
+            "median-cut" | "median" => Ok(PaletteKind::MedianCut),
+            "octree" => Ok(PaletteKind::Octree),
+            _ => Err(AurenaError::InvalidOption {
+                option: "palette".to_string(),
+                value: s.to_string(),
+            }),
+        }
+    }
+}
+
+/// build the palette for an image, adapting it to the pixels unless fixed
+pub fn build_palette(img: &RgbImage, kind: PaletteKind, max_colors: usize, metric: ColorMetric) -> Vec<Color> {
+    let max_colors = max_colors.clamp(2, MAX_PALETTE_COLORS);
+    match kind {
+        PaletteKind::Fixed => get_palette(),
+        PaletteKind::MedianCut => median_cut_palette(img, max_colors, metric),
+        PaletteKind::Octree => octree_palette(img, max_colors, metric),
+    }
+}
+
+/// build the palette a whole clip is encoded with, from its first frame
+///
+/// the first frame can be unrepresentative, such as a fade in from black, so
+/// adaptive palettes keep part of the fixed palette for whatever comes later
+pub fn clip_palette(first_frame: &RgbImage, kind: PaletteKind, max_colors: usize, metric: ColorMetric) -> Vec<Color> {
+    if kind == PaletteKind::Fixed {
+        return get_palette();
+    }
+
+    let max_colors = max_colors.clamp(2, MAX_PALETTE_COLORS);
+    let fixed = get_palette();
+    let adaptive = max_colors - (max_colors / 2).min(fixed.len());
+    let mut palette = build_palette(first_frame, kind, adaptive, metric);
+
+    // an even spread over the fixed palette keeps a few shades of every hue
+    let seeds = max_colors.saturating_sub(palette.len()).min(fixed.len());
+    for i in 0..seeds {
+        let color = fixed[i * fixed.len() / seeds];
+        if !palette.contains(&color) {
+            palette.push(color);
+        }
+    }
+    palette
+}
 
 /// generate color palette for sixel rendering
 pub fn get_palette() -> Vec<Color> {
@@ -101,50 +173,76 @@ pub fn get_palette() -> Vec<Color> {
 }
 
 /// build a color lookup table for fast color quantization
-pub fn build_color_lookup_table(palette: &[Color]) -> Vec<usize> {
-    const QUANT: usize = 8; // 8x8x8 = 512 entries
-    const STEP: usize = 256 / QUANT;
-    
-    let mut lut = vec![0; QUANT * QUANT * QUANT];
-    
-    for r in 0..QUANT {
-        for g in 0..QUANT {
-            for b in 0..QUANT {
+pub fn build_color_lookup_table(palette: &[Color], metric: ColorMetric) -> Vec<usize> {
+    let palette: Vec<[f64; 3]> = palette
+        .iter()
+        .map(|color| match color {
+            Color::Rgb { r, g, b } => metric.coordinates([*r, *g, *b]),
+            // never matched: farther than any real color
+            _ => [f64::INFINITY; 3],
+        })
+        .collect();
+
+    let mut lut = vec![0; LUT_QUANT * LUT_QUANT * LUT_QUANT];
+    // the perceptual metrics are costly enough per cell to spread the table over all cores
+    lut.par_chunks_mut(LUT_QUANT * LUT_QUANT).enumerate().for_each(|(r, plane)| {
+        for g in 0..LUT_QUANT {
+            for b in 0..LUT_QUANT {
+                // sample the center of each cell
                 let rgb = [
-                    (r * STEP).min(255) as u8,
-                    (g * STEP).min(255) as u8,
-                    (b * STEP).min(255) as u8,
+                    (r * LUT_STEP + LUT_STEP / 2).min(255) as u8,
+                    (g * LUT_STEP + LUT_STEP / 2).min(255) as u8,
+                    (b * LUT_STEP + LUT_STEP / 2).min(255) as u8,
                 ];
-                let pixel = Rgb(rgb);
-                let color_idx = nearest_color_weighted(&pixel, palette);
-                let lut_idx = r * QUANT * QUANT + g * QUANT + b;
-                lut[lut_idx] = color_idx;
+                plane[g * LUT_QUANT + b] = nearest_color(metric.coordinates(rgb), &palette, metric);
             }
         }
-    }
+    });
     lut
 }
 
-/// find the nearest color using perceptual weighting
-pub fn nearest_color_weighted(pixel: &Rgb<u8>, palette: &[Color]) -> usize {
+/// index of a pixel in the table built by `build_color_lookup_table`
+#[inline]
+pub fn lut_index(pixel: &Rgb<u8>) -> usize {
+    let shift = 8 - LUT_BITS;
+    (pixel[0] as usize >> shift) << (LUT_BITS * 2)
+        | (pixel[1] as usize >> shift) << LUT_BITS
+        | pixel[2] as usize >> shift
+}
+
+/// index of the palette color closest to `color`, both given as `metric` coordinates
+pub fn nearest_color(color: [f64; 3], palette: &[[f64; 3]], metric: ColorMetric) -> usize {
     let mut min_dist = f64::MAX;
     let mut idx = 0;
 
-    for (i, color) in palette.iter().enumerate() {
-        if let Color::Rgb { r, g, b } = color {
-            let dr = pixel[0] as f64 - *r as f64;
-            let dg = pixel[1] as f64 - *g as f64;
-            let db = pixel[2] as f64 - *b as f64;
-            
-            // Enhanced perceptual weighting (based on human eye sensitivity)
-            let dist = 0.21 * dr * dr + 0.72 * dg * dg + 0.07 * db * db;
-
-            if dist < min_dist {
-                min_dist = dist;
-                idx = i;
-            }
+    for (i, entry) in palette.iter().enumerate() {
+        let dist = metric.squared_distance(color, *entry);
+        if dist < min_dist {
+            min_dist = dist;
+            idx = i;
         }
     }
 
     idx
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    #[test]
+    fn clip_palettes_survive_a_black_first_frame() {
+        let black = RgbImage::from_pixel(64, 64, Rgb([0, 0, 0]));
+        for kind in [PaletteKind::MedianCut, PaletteKind::Octree] {
+            for max_colors in [2, 16, 64, 256] {
+                let palette = clip_palette(&black, kind, max_colors, ColorMetric::Rgb);
+                assert!(palette.len() <= max_colors);
+                if max_colors >= 16 {
+                    assert!(palette.contains(&Color::Rgb { r: 255, g: 255, b: 255 }), "{:?} {}", kind, max_colors);
+                    assert!(palette.len() > max_colors / 4, "{:?}: {} colors", kind, palette.len());
+                }
+            }
+        }
+    }
+}
@@ -1,53 +1,78 @@
 use crate::errors::{AurenaError, Result};
-use crate::terminal::get_terminal_size;
-use crate::sixel::color::{get_palette, build_color_lookup_table};
+use crate::SixelMode;
+use crate::layout::scale_to_terminal;
+use crate::sixel::color::{build_palette, build_color_lookup_table};
+use crate::sixel::{SixelOptions, dither::palette_indices, push_fmt, rle};
 use image::{DynamicImage};
 use crossterm::style::Color;
+use rayon::prelude::*;
+use std::io::Write;
+use std::ops::Range;
 
-/// convert an image to color sixel format
-pub fn image_sixel_convert(img: &DynamicImage) -> Result<String> {
-    let (term_w, term_h) = get_terminal_size()
-        .ok_or(AurenaError::TerminalSizeError)?;
-
-    let max_width = term_w * 6;
-    let max_height = term_h * 2;
-    
-    let img = if img.width() > max_width || img.height() > max_height {
-        img.resize(max_width, max_height, image::imageops::FilterType::Lanczos3)
-    } else {
-        img.clone()
-    };
+/// convert an image to color sixel format, writing it to `out`
+pub fn image_sixel_convert(img: &DynamicImage, options: &SixelOptions, out: &mut impl Write) -> Result<()> {
+    let img = scale_to_terminal(img, &SixelMode::Color, &options.size)?;
     
-    let palette = get_palette();
-    let color_lut = build_color_lookup_table(&palette);
-    let mut sixel_buffer = Vec::new();
-    let mut color_bands: Vec<Vec<u8>> = vec![Vec::new(); palette.len()];
+    let palette = build_palette(&img.to_rgb8(), options.palette, options.colors, options.metric);
+    let color_lut = build_color_lookup_table(&palette, options.metric);
+    let mut band_buffers = Vec::new();
     
-    video_sixel_convert(&img, &palette, &color_lut, &mut sixel_buffer, &mut color_bands)
+    video_sixel_convert(&img, &palette, &color_lut, &mut band_buffers, options, out)
 }
 
+/// sixel introducer; the background is filled as usual
+const SIXEL_START: &[u8] = b"\x1bPq";
+
+/// sixel introducer with P2=1, so pixels no color sets keep what is already on screen
+const SIXEL_START_TRANSPARENT: &[u8] = b"\x1bP0;1q";
+
 /// optimized sixel conversion for video frames (reuses buffers)
+///
+/// bands are encoded in parallel into `band_buffers`, one per 6-row band, then joined in order
 pub fn video_sixel_convert(
     img: &DynamicImage, 
     palette: &[Color],
     color_lut: &[usize],
-    sixel_buffer: &mut Vec<u8>,
-    color_bands: &mut [Vec<u8>],
-) -> Result<String> {
-    const QUANT: usize = 8;
-    const STEP: usize = 256 / QUANT;
-    
+    band_buffers: &mut Vec<Vec<u8>>,
+    options: &SixelOptions,
+    out: &mut impl Write,
+) -> Result<()> {
+    encode_sixel(img, None, palette, color_lut, band_buffers, options, out)
+}
+
+/// convert the changed strips of a video frame as one image drawn over the previous frame
+///
+/// `strips` are the pixel rows of `img` to draw; every other row, including those
+/// of the last band past the end of the image, is left transparent so it keeps
+/// what is already on screen. the palette is sent once for all strips
+pub fn region_sixel_convert(
+    img: &DynamicImage,
+    strips: &[Range<u32>],
+    palette: &[Color],
+    color_lut: &[usize],
+    band_buffers: &mut Vec<Vec<u8>>,
+    options: &SixelOptions,
+    out: &mut impl Write,
+) -> Result<()> {
+    encode_sixel(img, Some(strips), palette, color_lut, band_buffers, options, out)
+}
+
+fn encode_sixel(
+    img: &DynamicImage,
+    strips: Option<&[Range<u32>]>,
+    palette: &[Color],
+    color_lut: &[usize],
+    band_buffers: &mut Vec<Vec<u8>>,
+    options: &SixelOptions,
+    out: &mut impl Write,
+) -> Result<()> {
+    let introducer = if strips.is_some() { SIXEL_START_TRANSPARENT } else { SIXEL_START };
     let (width, height) = (img.width(), img.height());
     let rgb_img = img.to_rgb8();
 
-    sixel_buffer.clear();
-    
-    let estimated_size = width as usize * height as usize / 3 + palette.len() * 50;
-    if sixel_buffer.capacity() < estimated_size {
-        sixel_buffer.reserve(estimated_size);
-    }
-
-    sixel_buffer.extend_from_slice(b"\x1bPq");
+    // palette definitions go out as one write; bands follow straight from their buffers
+    let mut header = Vec::with_capacity(palette.len() * 20 + introducer.len());
+    header.extend_from_slice(introducer);
 
     // define palette
     for (i, color) in palette.iter().enumerate() {
@@ -55,65 +80,161 @@ pub fn video_sixel_convert(
             let r = (*r as u32 * 100) / 255;
             let g = (*g as u32 * 100) / 255;
             let b = (*b as u32 * 100) / 255;
-            let palette_def = format!("#{};2;{};{};{}", i, r, g, b);
-            sixel_buffer.extend_from_slice(palette_def.as_bytes());
+            push_fmt(&mut header, format_args!("#{};2;{};{};{}", i, r, g, b));
         }
     }
 
-    // process in 6-pixel high bands
-    for y in (0..height).step_by(6) {
-        // Clear color band buffers
-        for band in color_bands.iter_mut() {
-            band.clear();
-            if band.capacity() < width as usize {
-                band.reserve(width as usize);
+    // quantize (and optionally dither) the whole frame before band packing
+    let mut indices = Vec::new();
+    palette_indices(&rgb_img, palette, color_lut, options.dither, &mut indices);
+
+    // rows outside the strips go to one extra color that is never drawn
+    let transparent = palette.len();
+    if let Some(strips) = strips {
+        for (y, row) in (0..).zip(indices.chunks_mut((width as usize).max(1))) {
+            if !strips.iter().any(|strip| strip.contains(&y)) {
+                row.fill(transparent);
             }
         }
-        
-        // build pixel data for each x position
-        for x in 0..width {
-            let mut color_sixels = vec![0u8; palette.len()];
-            
-            for bit in 0..6 {
-                if y + bit < height {
-                    let pixel = rgb_img.get_pixel(x, y + bit);
-                    
-                    // Fast color lookup using quantized LUT
-                    let r_idx = (pixel[0] as usize / STEP).min(QUANT - 1);
-                    let g_idx = (pixel[1] as usize / STEP).min(QUANT - 1);
-                    let b_idx = (pixel[2] as usize / STEP).min(QUANT - 1);
-                    let lut_idx = r_idx * QUANT * QUANT + g_idx * QUANT + b_idx;
-                    
-                    let color_idx = color_lut[lut_idx];
-                    color_sixels[color_idx] |= 1 << bit;
-                }
-            }
-            
-            // add to appropriate color bands
-            for (color_idx, &sixel_char) in color_sixels.iter().enumerate() {
-                color_bands[color_idx].push(sixel_char + 63);
-            }
+    }
+
+    // process in 6-pixel high bands; each one only reads its own rows
+    let band_len = width as usize * 6;
+    band_buffers.resize_with(height.div_ceil(6) as usize, Vec::new);
+    indices
+        .par_chunks(band_len.max(1))
+        .zip(band_buffers.par_iter_mut())
+        .for_each_init(
+            || vec![Vec::new(); transparent + 1],
+            |color_bands, (rows, output)| {
+                encode_band(rows, width as usize, color_bands, transparent, output, options.compress)
+            },
+        );
+
+    write_sixel(out, &header, band_buffers).map_err(|e| AurenaError::IoError { err: e })
+}
+
+/// the palette header, then the bands separated by graphics new lines
+fn write_sixel(out: &mut impl Write, header: &[u8], bands: &[Vec<u8>]) -> std::io::Result<()> {
+    out.write_all(header)?;
+    for (i, band) in bands.iter().enumerate() {
+        if i > 0 {
+            out.write_all(b"-")?;
         }
-        
-        // output only non-empty color bands
-        for (color_idx, band) in color_bands.iter().enumerate() {
-            if band.iter().any(|&c| c != 63) { // 63 is empty sixel
-                let color_header = format!("#{}", color_idx);
-                sixel_buffer.extend_from_slice(color_header.as_bytes());
-                sixel_buffer.extend_from_slice(band);
-                sixel_buffer.push(b'$');
-            }
+        out.write_all(band)?;
+    }
+    out.write_all(b"\x1b\\")
+}
+
+/// encode up to six rows of palette indices as one sixel band, drawing the first `colors`
+fn encode_band(
+    rows: &[usize],
+    width: usize,
+    color_bands: &mut [Vec<u8>],
+    colors: usize,
+    output: &mut Vec<u8>,
+    compress: bool,
+) {
+    output.clear();
+
+    // reset color band buffers to empty sixels
+    for band in color_bands.iter_mut() {
+        band.clear();
+        band.resize(width, 0);
+    }
+
+    // set one bit per row of the band in the chosen color's buffer
+    for (bit, row) in rows.chunks(width).enumerate() {
+        for (x, &color_idx) in row.iter().enumerate() {
+            color_bands[color_idx][x] |= 1 << bit;
         }
-        
-        if y + 6 < height {
-            sixel_buffer.push(b'-');
+    }
+
+    // output only non-empty color bands
+    for (color_idx, band) in color_bands[..colors].iter_mut().enumerate() {
+        if band.iter().any(|&c| c != 0) {
+            band.iter_mut().for_each(|c| *c += rle::EMPTY_SIXEL);
+            let color_header = format!("#{}", color_idx);
+            output.extend_from_slice(color_header.as_bytes());
+            rle::push_band(output, band, compress);
+            output.push(b'$');
         }
     }
+}
 
-    sixel_buffer.extend_from_slice(b"\x1b\\");
-    
-    String::from_utf8(sixel_buffer.clone())
-        .map_err(|e| AurenaError::SixelConversionError { 
-            msg: format!("Invalid UTF-8 in sixel data: {}", e) 
-        })
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sixel::color::PaletteKind;
+    use image::{Rgb, RgbImage};
+    use std::time::{Duration, Instant};
+
+    fn encoded_size(img: &DynamicImage, compress: bool) -> usize {
+        // an adaptive palette keeps the header from outweighing the bands
+        let options = SixelOptions { compress, palette: PaletteKind::MedianCut, ..SixelOptions::default() };
+        let palette = build_palette(&img.to_rgb8(), options.palette, options.colors, options.metric);
+        let color_lut = build_color_lookup_table(&palette, options.metric);
+        let mut out = Vec::new();
+        video_sixel_convert(img, &palette, &color_lut, &mut Vec::new(), &options, &mut out).unwrap();
+        out.len()
+    }
+
+    #[test]
+    fn flat_frames_compress() {
+        let img = DynamicImage::ImageRgb8(RgbImage::from_pixel(320, 60, Rgb([40, 90, 200])));
+        let (compressed, plain) = (encoded_size(&img, true), encoded_size(&img, false));
+        assert!(compressed * 10 < plain, "{} bytes compressed, {} uncompressed", compressed, plain);
+    }
+
+    #[test]
+    fn rows_outside_strips_stay_transparent() {
+        let img = DynamicImage::ImageRgb8(RgbImage::from_pixel(8, 18, Rgb([255, 255, 255])));
+        let options = SixelOptions::default();
+        let palette = build_palette(&img.to_rgb8(), options.palette, options.colors, options.metric);
+        let color_lut = build_color_lookup_table(&palette, options.metric);
+        let mut out = Vec::new();
+        let strips = [0..6, 12..18];
+        region_sixel_convert(&img, &strips, &palette, &color_lut, &mut Vec::new(), &options, &mut out).unwrap();
+
+        assert!(out.starts_with(SIXEL_START_TRANSPARENT));
+        // the band between the strips draws nothing
+        assert!(out.windows(4).any(|w| w == b"$--#"), "{:?}", String::from_utf8_lossy(&out));
+    }
+
+    /// times frame encoding alone, with the palette and lookup table built up front;
+    /// run with `cargo test --release encoding_speed -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn encoding_speed() {
+        const RUNS: u32 = 20;
+        let img = DynamicImage::ImageRgb8(RgbImage::from_fn(1280, 720, |x, y| {
+            Rgb([(x * 255 / 1279) as u8, (y * 255 / 719) as u8, ((x + y) % 256) as u8])
+        }));
+        let options = SixelOptions::default();
+        let palette = build_palette(&img.to_rgb8(), options.palette, options.colors, options.metric);
+        let color_lut = build_color_lookup_table(&palette, options.metric);
+
+        let time_runs = |threads: usize| -> Duration {
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+            pool.install(|| {
+                let (mut band_buffers, mut out) = (Vec::new(), Vec::new());
+                // the first run warms up allocations and caches
+                video_sixel_convert(&img, &palette, &color_lut, &mut band_buffers, &options, &mut out).unwrap();
+                let start = Instant::now();
+                for _ in 0..RUNS {
+                    out.clear();
+                    video_sixel_convert(&img, &palette, &color_lut, &mut band_buffers, &options, &mut out).unwrap();
+                }
+                start.elapsed() / RUNS
+            })
+        };
+
+        let threads = rayon::current_num_threads();
+        let (single, parallel) = (time_runs(1), time_runs(threads));
+        println!("1280x720 frame, {} runs", RUNS);
+        println!("1 thread:   {:8.2} ms per frame", single.as_secs_f64() * 1000.0);
+        println!("{} threads: {:8.2} ms per frame ({:.2}x)",
+                threads, parallel.as_secs_f64() * 1000.0,
+                single.as_secs_f64() / parallel.as_secs_f64().max(f64::EPSILON));
+    }
+}
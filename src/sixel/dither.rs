@@ -0,0 +1,196 @@
+use crate::errors::{AurenaError, Result};
+use crate::sixel::color::lut_index;
+use crossterm::style::Color;
+use image::{Rgb, RgbImage};
+
+/// error diffusion weights as (dx, dy, weight)
+type Kernel = &'static [(i32, i32, f32)];
+
+/// how pixels are reduced to the palette before band packing
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DitherMode {
+    /// nearest color / plain threshold
+    None,
+    FloydSteinberg,
+    Atkinson,
+    JarvisJudiceNinke,
+    /// ordered dithering with an n x n Bayer matrix (2, 4 or 8)
+    Bayer(usize),
+}
+
+impl DitherMode {
+    pub fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(DitherMode::None),
+            "floyd-steinberg" | "fs" => Ok(DitherMode::FloydSteinberg),
+            "atkinson" => Ok(DitherMode::Atkinson),
+            "jarvis" | "jjn" => Ok(DitherMode::JarvisJudiceNinke),
+            "bayer2" => Ok(DitherMode::Bayer(2)),
+            "bayer4" | "bayer" => Ok(DitherMode::Bayer(4)),
+            "bayer8" => Ok(DitherMode::Bayer(8)),
+            _ => Err(AurenaError::InvalidOption {
+                option: "dither".to_string(),
+                value: s.to_string(),
+            }),
+        }
+    }
+
+    /// error diffusion kernel plus its divisor
+    fn kernel(&self) -> Option<(Kernel, f32)> {
+        match self {
+            DitherMode::FloydSteinberg => Some((
+                &[(1, 0, 7.0), (-1, 1, 3.0), (0, 1, 5.0), (1, 1, 1.0)],
+                16.0,
+            )),
+            // atkinson only propagates 6/8 of the error, keeping highlights crisp
+            DitherMode::Atkinson => Some((
+                &[(1, 0, 1.0), (2, 0, 1.0), (-1, 1, 1.0), (0, 1, 1.0), (1, 1, 1.0), (0, 2, 1.0)],
+                8.0,
+            )),
+            DitherMode::JarvisJudiceNinke => Some((
+                &[
+                    (1, 0, 7.0), (2, 0, 5.0),
+                    (-2, 1, 3.0), (-1, 1, 5.0), (0, 1, 7.0), (1, 1, 5.0), (2, 1, 3.0),
+                    (-2, 2, 1.0), (-1, 2, 3.0), (0, 2, 5.0), (1, 2, 3.0), (2, 2, 1.0),
+                ],
+                48.0,
+            )),
+            DitherMode::None | DitherMode::Bayer(_) => None,
+        }
+    }
+}
+
+/// map every pixel to a palette index, row-major, applying the dither mode
+pub fn palette_indices(
+    img: &RgbImage,
+    palette: &[Color],
+    color_lut: &[usize],
+    mode: DitherMode,
+    indices: &mut Vec<usize>,
+) {
+    let (width, height) = (img.width() as usize, img.height() as usize);
+    indices.clear();
+    indices.reserve(width * height);
+
+    let palette_rgb: Vec<[f32; 3]> = palette
+        .iter()
+        .map(|color| match color {
+            Color::Rgb { r, g, b } => [*r as f32, *g as f32, *b as f32],
+            _ => [0.0; 3],
+        })
+        .collect();
+
+    let lookup = |rgb: [f32; 3]| -> usize {
+        let pixel = Rgb(rgb.map(|v| v.round().clamp(0.0, 255.0) as u8));
+        color_lut[lut_index(&pixel)]
+    };
+
+    match mode {
+        DitherMode::None => {
+            indices.extend(img.pixels().map(|pixel| color_lut[lut_index(pixel)]));
+        }
+        DitherMode::Bayer(size) => {
+            let matrix = bayer_matrix(size);
+            // spread roughly matches the spacing between palette colors
+            let spread = (255.0 / (palette.len() as f32).cbrt()).min(64.0);
+            for (x, y, pixel) in img.enumerate_pixels() {
+                let offset = (matrix[(y as usize % size) * size + x as usize % size] - 0.5) * spread;
+                indices.push(lookup(pixel.0.map(|v| v as f32 + offset)));
+            }
+        }
+        _ => {
+            let mut pixels: Vec<[f32; 3]> = img.pixels().map(|p| p.0.map(|v| v as f32)).collect();
+            diffuse(&mut pixels, width, height, mode, |rgb| {
+                let idx = lookup(rgb);
+                indices.push(idx);
+                palette_rgb[idx]
+            });
+        }
+    }
+}
+
+/// per-pixel on/off mask for monochrome output, row-major
+pub fn monochrome_mask(img: &RgbImage, mode: DitherMode, mask: &mut Vec<bool>) {
+    let (width, height) = (img.width() as usize, img.height() as usize);
+    mask.clear();
+    mask.reserve(width * height);
+
+    let brightness = |pixel: &Rgb<u8>| (pixel[0] as f32 + pixel[1] as f32 + pixel[2] as f32) / 3.0;
+
+    match mode {
+        DitherMode::None => {
+            mask.extend(img.pixels().map(|pixel| brightness(pixel) > 128.0));
+        }
+        DitherMode::Bayer(size) => {
+            let matrix = bayer_matrix(size);
+            for (x, y, pixel) in img.enumerate_pixels() {
+                let threshold = matrix[(y as usize % size) * size + x as usize % size] * 255.0;
+                mask.push(brightness(pixel) > threshold);
+            }
+        }
+        _ => {
+            let mut pixels: Vec<[f32; 1]> = img.pixels().map(|p| [brightness(p)]).collect();
+            diffuse(&mut pixels, width, height, mode, |[value]| {
+                let on = value > 128.0;
+                mask.push(on);
+                if on { [255.0] } else { [0.0] }
+            });
+        }
+    }
+}
+
+/// normalized n x n Bayer threshold matrix with values in (0, 1)
+fn bayer_matrix(size: usize) -> Vec<f32> {
+    let mut matrix = vec![0u32];
+    let mut n = 1;
+    while n < size {
+        let mut next = vec![0u32; 4 * n * n];
+        for y in 0..n {
+            for x in 0..n {
+                let v = 4 * matrix[y * n + x];
+                next[y * 2 * n + x] = v;
+                next[y * 2 * n + x + n] = v + 2;
+                next[(y + n) * 2 * n + x] = v + 3;
+                next[(y + n) * 2 * n + x + n] = v + 1;
+            }
+        }
+        matrix = next;
+        n *= 2;
+    }
+
+    let cells = (n * n) as f32;
+    matrix.iter().map(|&v| (v as f32 + 0.5) / cells).collect()
+}
+
+/// run error diffusion in raster order; `quantize` picks the output value for each pixel
+fn diffuse<const C: usize>(
+    pixels: &mut [[f32; C]],
+    width: usize,
+    height: usize,
+    mode: DitherMode,
+    mut quantize: impl FnMut([f32; C]) -> [f32; C],
+) {
+    let Some((kernel, divisor)) = mode.kernel() else {
+        return;
+    };
+
+    for y in 0..height {
+        for x in 0..width {
+            let old = pixels[y * width + x];
+            let new = quantize(old);
+
+            let error: [f32; C] = std::array::from_fn(|c| old[c] - new[c]);
+
+            for &(dx, dy, weight) in kernel {
+                let (nx, ny) = (x as i32 + dx, y as i32 + dy);
+                if nx < 0 || nx >= width as i32 || ny >= height as i32 {
+                    continue;
+                }
+                let neighbor = &mut pixels[ny as usize * width + nx as usize];
+                for (value, e) in neighbor.iter_mut().zip(error) {
+                    *value += e * weight / divisor;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use crate::errors::{AurenaError, Result};
+
+/// weights of the original gamma-encoded RGB distance
+const RGB_WEIGHTS: [f64; 3] = [0.21, 0.72, 0.07];
+
+/// D65 white point, for CIELAB
+const WHITE: [f64; 3] = [0.95047, 1.0, 1.08883];
+
+/// how the distance between two colors is measured when matching pixels to
+/// the palette and when building adaptive palettes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorMetric {
+    /// weighted Euclidean distance on gamma-encoded sRGB
+    Rgb,
+    /// Euclidean distance on linear-light RGB
+    LinearRgb,
+    /// CIE 1976 ΔE: Euclidean distance in CIELAB
+    Cie76,
+    /// CIEDE2000 ΔE: CIELAB with hue, chroma and lightness corrections
+    Ciede2000,
+    /// Euclidean distance in OKLab
+    Oklab,
+}
+
+impl ColorMetric {
+    pub fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "rgb" => Ok(ColorMetric::Rgb),
+            "linear-rgb" | "linear" => Ok(ColorMetric::LinearRgb),
+            "cie76" | "lab" => Ok(ColorMetric::Cie76),
+            "ciede2000" | "de2000" => Ok(ColorMetric::Ciede2000),
+            "oklab" => Ok(ColorMetric::Oklab),
+            _ => Err(AurenaError::InvalidOption {
+                option: "metric".to_string(),
+                value: s.to_string(),
+            }),
+        }
+    }
+
+    /// the color in the space this metric measures in
+    pub fn coordinates(self, rgb: [u8; 3]) -> [f64; 3] {
+        match self {
+            ColorMetric::Rgb => rgb.map(f64::from),
+            ColorMetric::LinearRgb => rgb.map(srgb_to_linear),
+            ColorMetric::Cie76 | ColorMetric::Ciede2000 => lab(rgb),
+            ColorMetric::Oklab => oklab(rgb),
+        }
+    }
+
+    /// squared distance between two colors given as `coordinates`
+    pub fn squared_distance(self, a: [f64; 3], b: [f64; 3]) -> f64 {
+        let d = [a[0] - b[0], a[1] - b[1], a[2] - b[2]];
+        match self {
+            ColorMetric::Rgb => RGB_WEIGHTS[0] * d[0] * d[0] + RGB_WEIGHTS[1] * d[1] * d[1] + RGB_WEIGHTS[2] * d[2] * d[2],
+            ColorMetric::Ciede2000 => ciede2000(a, b).powi(2),
+            ColorMetric::LinearRgb | ColorMetric::Cie76 | ColorMetric::Oklab => d[0] * d[0] + d[1] * d[1] + d[2] * d[2],
+        }
+    }
+
+    /// `coordinates` spread over 0..=255 per axis, so the octree can split them bit by bit
+    pub fn octree_key(self, rgb: [u8; 3]) -> [u8; 3] {
+        let (min, max) = match self {
+            ColorMetric::Rgb => return rgb,
+            ColorMetric::LinearRgb => ([0.0; 3], [1.0; 3]),
+            ColorMetric::Cie76 | ColorMetric::Ciede2000 => ([0.0, -128.0, -128.0], [100.0, 128.0, 128.0]),
+            // sRGB colors stay within about ±0.32 on the OKLab a and b axes
+            ColorMetric::Oklab => ([0.0, -0.4, -0.4], [1.0, 0.4, 0.4]),
+        };
+        let coordinates = self.coordinates(rgb);
+        [0, 1, 2].map(|axis| {
+            let scaled = (coordinates[axis] - min[axis]) / (max[axis] - min[axis]) * 255.0;
+            scaled.round().clamp(0.0, 255.0) as u8
+        })
+    }
+}
+
+fn srgb_to_linear(value: u8) -> f64 {
+    let value = value as f64 / 255.0;
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// CIELAB under D65, as (L*, a*, b*)
+fn lab(rgb: [u8; 3]) -> [f64; 3] {
+    let [r, g, b] = rgb.map(srgb_to_linear);
+    let xyz = [
+        0.4124564 * r + 0.3575761 * g + 0.1804375 * b,
+        0.2126729 * r + 0.7151522 * g + 0.0721750 * b,
+        0.0193339 * r + 0.1191920 * g + 0.9503041 * b,
+    ];
+
+    let f = |t: f64| {
+        const DELTA: f64 = 6.0 / 29.0;
+        if t > DELTA.powi(3) { t.cbrt() } else { t / (3.0 * DELTA * DELTA) + 4.0 / 29.0 }
+    };
+    let [fx, fy, fz] = [0, 1, 2].map(|axis| f(xyz[axis] / WHITE[axis]));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+/// OKLab, as (L, a, b)
+fn oklab(rgb: [u8; 3]) -> [f64; 3] {
+    let [r, g, b] = rgb.map(srgb_to_linear);
+    let l = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+    let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+    let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+    [
+        0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+        1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+        0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+    ]
+}
+
+/// CIEDE2000 color difference between two CIELAB colors
+fn ciede2000(lab1: [f64; 3], lab2: [f64; 3]) -> f64 {
+    let [l1, a1, b1] = lab1;
+    let [l2, a2, b2] = lab2;
+    let pow25_7 = 25f64.powi(7);
+
+    // stretch a* for near-neutral colors
+    let c_bar = (a1.hypot(b1) + a2.hypot(b2)) / 2.0;
+    let g = 0.5 * (1.0 - (c_bar.powi(7) / (c_bar.powi(7) + pow25_7)).sqrt());
+    let (a1, a2) = (a1 * (1.0 + g), a2 * (1.0 + g));
+
+    let (c1, c2) = (a1.hypot(b1), a2.hypot(b2));
+    let hue = |b: f64, a: f64| if a == 0.0 && b == 0.0 { 0.0 } else { b.atan2(a).to_degrees().rem_euclid(360.0) };
+    let (h1, h2) = (hue(b1, a1), hue(b2, a2));
+    let achromatic = c1 * c2 == 0.0;
+
+    let delta_l = l2 - l1;
+    let delta_c = c2 - c1;
+    let delta_h = match h2 - h1 {
+        _ if achromatic => 0.0,
+        d if d > 180.0 => d - 360.0,
+        d if d < -180.0 => d + 360.0,
+        d => d,
+    };
+    let delta_h = 2.0 * (c1 * c2).sqrt() * (delta_h / 2.0).to_radians().sin();
+
+    let l_bar = (l1 + l2) / 2.0;
+    let c_bar = (c1 + c2) / 2.0;
+    let h_bar = if achromatic {
+        h1 + h2
+    } else if (h1 - h2).abs() <= 180.0 {
+        (h1 + h2) / 2.0
+    } else if h1 + h2 < 360.0 {
+        (h1 + h2 + 360.0) / 2.0
+    } else {
+        (h1 + h2 - 360.0) / 2.0
+    };
+
+    let cos = |degrees: f64| degrees.to_radians().cos();
+    let t = 1.0 - 0.17 * cos(h_bar - 30.0) + 0.24 * cos(2.0 * h_bar) + 0.32 * cos(3.0 * h_bar + 6.0)
+        - 0.20 * cos(4.0 * h_bar - 63.0);
+    let rotation = 30.0 * (-((h_bar - 275.0) / 25.0).powi(2)).exp();
+    let r_c = 2.0 * (c_bar.powi(7) / (c_bar.powi(7) + pow25_7)).sqrt();
+    let r_t = -(2.0 * rotation).to_radians().sin() * r_c;
+
+    let s_l = 1.0 + 0.015 * (l_bar - 50.0).powi(2) / (20.0 + (l_bar - 50.0).powi(2)).sqrt();
+    let s_c = 1.0 + 0.045 * c_bar;
+    let s_h = 1.0 + 0.015 * c_bar * t;
+
+    let (dl, dc, dh) = (delta_l / s_l, delta_c / s_c, delta_h / s_h);
+    (dl * dl + dc * dc + dh * dh + r_t * dc * dh).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sixel::color::{PaletteKind, build_color_lookup_table, build_palette, lut_index};
+    use crossterm::style::Color;
+    use image::RgbImage;
+    use image::imageops::FilterType;
+
+    const METRICS: [ColorMetric; 5] = [
+        ColorMetric::Rgb,
+        ColorMetric::LinearRgb,
+        ColorMetric::Cie76,
+        ColorMetric::Ciede2000,
+        ColorMetric::Oklab,
+    ];
+
+    /// adaptive palette size; small enough that the ciede2000 tables build quickly
+    const COLORS: usize = 16;
+
+    /// largest mean CIEDE2000 ΔE allowed for each metric, in `METRICS` order, about
+    /// 15% above what each one reaches at 160 pixels and `COLORS` colors
+    const BOUNDS: [(&str, PaletteKind, [f64; 5]); 9] = [
+        ("art.jpg", PaletteKind::Fixed, [13.1, 12.7, 12.4, 11.6, 12.6]),
+        ("art.jpg", PaletteKind::MedianCut, [9.3, 9.1, 8.5, 8.3, 8.5]),
+        ("art.jpg", PaletteKind::Octree, [10.8, 10.2, 8.9, 8.7, 10.0]),
+        ("pfp.jpeg", PaletteKind::Fixed, [1.2, 1.2, 1.2, 1.2, 1.5]),
+        ("pfp.jpeg", PaletteKind::MedianCut, [1.3, 1.5, 1.4, 1.4, 1.2]),
+        ("pfp.jpeg", PaletteKind::Octree, [3.6, 5.9, 2.6, 2.5, 2.4]),
+        ("sz.png", PaletteKind::Fixed, [9.5, 9.8, 9.2, 8.9, 9.2]),
+        ("sz.png", PaletteKind::MedianCut, [3.2, 3.6, 3.2, 3.1, 3.3]),
+        ("sz.png", PaletteKind::Octree, [4.5, 4.8, 3.3, 3.2, 3.8]),
+    ];
+
+    fn sample(name: &str) -> RgbImage {
+        let path = format!("{}/image/{}", env!("CARGO_MANIFEST_DIR"), name);
+        image::open(&path).unwrap().resize(160, 160, FilterType::Triangle).to_rgb8()
+    }
+
+    /// mean CIEDE2000 ΔE between an image and its palette-mapped pixels
+    fn mean_error(img: &RgbImage, palette: &[Color], color_lut: &[usize]) -> f64 {
+        let total: f64 = img
+            .pixels()
+            .map(|pixel| {
+                let Color::Rgb { r, g, b } = palette[color_lut[lut_index(pixel)]] else { unreachable!() };
+                ciede2000(lab(pixel.0), lab([r, g, b]))
+            })
+            .sum();
+        total / img.pixels().len() as f64
+    }
+
+    #[test]
+    fn palettes_stay_within_error_bounds() {
+        let images: Vec<(&str, RgbImage)> = ["art.jpg", "pfp.jpeg", "sz.png"].map(|name| (name, sample(name))).into();
+
+        for (i, metric) in METRICS.into_iter().enumerate() {
+            // the fixed palette does not depend on the image, so its table is built once
+            let fixed = build_palette(&images[0].1, PaletteKind::Fixed, COLORS, metric);
+            let fixed_lut = build_color_lookup_table(&fixed, metric);
+
+            for (name, kind, bounds) in BOUNDS {
+                let img = &images.iter().find(|(sample, _)| *sample == name).unwrap().1;
+                let error = if kind == PaletteKind::Fixed {
+                    mean_error(img, &fixed, &fixed_lut)
+                } else {
+                    let palette = build_palette(img, kind, COLORS, metric);
+                    mean_error(img, &palette, &build_color_lookup_table(&palette, metric))
+                };
+                assert!(
+                    error <= bounds[i],
+                    "{} {:?} {:?}: mean ΔE {:.2} above {:.1}",
+                    name, kind, metric, error, bounds[i]
+                );
+            }
+        }
+    }
+}
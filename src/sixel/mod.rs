@@ -1,17 +1,73 @@
 pub mod color;
 pub mod converter;
+pub mod dither;
+pub mod metric;
 pub mod monochrome;
+pub mod quantize;
+pub mod rle;
 
 pub use converter::image_sixel_convert;
 pub use monochrome::monochrome_sixel_convert;
 
 use crate::SixelMode;
-use crate::errors::Result;
+use crate::blocks::blocks_image_convert;
+use crate::iterm::iterm_image_convert;
+use crate::kitty::kitty_image_convert;
+use crate::layout::SizeOptions;
+use crate::errors::{AurenaError, Result};
+use color::{MAX_PALETTE_COLORS, PaletteKind};
+use dither::DitherMode;
+use metric::ColorMetric;
 use image::DynamicImage;
+use std::io::Write;
 
-pub fn convert_image(img: &DynamicImage, mode: SixelMode) -> Result<String> {
-    match mode {
-        SixelMode::Color => image_sixel_convert(img),
-        SixelMode::Monochrome => monochrome_sixel_convert(img),
+/// encoder settings shared by the color and monochrome converters
+#[derive(Debug, Clone)]
+pub struct SixelOptions {
+    /// emit DECGRI repeat sequences for runs of identical sixels
+    pub compress: bool,
+    /// fixed or adaptive palette for color mode
+    pub palette: PaletteKind,
+    /// upper bound on adaptive palette size
+    pub colors: usize,
+    /// color distance for matching pixels and building adaptive palettes
+    pub metric: ColorMetric,
+    /// dithering applied before band packing
+    pub dither: DitherMode,
+    /// output size and fit mode
+    pub size: SizeOptions,
+    /// redraw only the parts of a video frame that changed since the last one
+    pub delta: bool,
+}
+
+impl Default for SixelOptions {
+    fn default() -> Self {
+        SixelOptions {
+            compress: true,
+            palette: PaletteKind::Fixed,
+            colors: MAX_PALETTE_COLORS,
+            metric: ColorMetric::Rgb,
+            dither: DitherMode::None,
+            size: SizeOptions::default(),
+            delta: true,
+        }
     }
 }
+
+/// convert a still image for the output mode, writing it to `out`
+pub fn convert_image(img: &DynamicImage, mode: SixelMode, options: &SixelOptions, out: &mut impl Write) -> Result<()> {
+    let output = match mode {
+        SixelMode::Color => return image_sixel_convert(img, options, out),
+        SixelMode::Monochrome => return monochrome_sixel_convert(img, options, out),
+        SixelMode::Kitty => kitty_image_convert(img, &options.size)?,
+        SixelMode::Iterm => iterm_image_convert(img, &options.size)?,
+        SixelMode::Blocks => blocks_image_convert(img, true, &options.size)?,
+        SixelMode::Blocks256 => blocks_image_convert(img, false, &options.size)?,
+    };
+    out.write_all(output.as_bytes()).map_err(|e| AurenaError::IoError { err: e })
+}
+
+/// append formatted text to an output buffer; writing into a Vec cannot fail
+pub fn push_fmt(buffer: &mut Vec<u8>, args: std::fmt::Arguments) {
+    let _ = buffer.write_fmt(args);
+}
@@ -1,55 +1,75 @@
 use crate::errors::{AurenaError, Result};
-use crate::terminal::get_terminal_size;
-use image::DynamicImage;
+use crate::SixelMode;
+use crate::layout::scale_to_terminal;
+use crate::sixel::{SixelOptions, dither::monochrome_mask, rle};
+use image::{DynamicImage, RgbImage};
+use rayon::prelude::*;
+use std::io::Write;
 
-/// convert image to monochrome sixel format
-pub fn monochrome_sixel_convert(img: &DynamicImage) -> Result<String> {
-    let (term_w, term_h) = get_terminal_size()
-        .ok_or(AurenaError::TerminalSizeError)?;
+/// convert image to monochrome sixel format, writing it to `out`
+pub fn monochrome_sixel_convert(img: &DynamicImage, options: &SixelOptions, out: &mut impl Write) -> Result<()> {
+    let img = scale_to_terminal(img, &SixelMode::Monochrome, &options.size)?;
+    monochrome_frame_convert(&img.to_rgb8(), options, out)
+}
 
-    let max_width = term_w * 6;
-    let max_height = term_h * 3;
+/// encode an already placed frame as monochrome sixel, writing it to `out`
+pub fn monochrome_frame_convert(rgb_img: &RgbImage, options: &SixelOptions, out: &mut impl Write) -> Result<()> {
+    let (width, height) = rgb_img.dimensions();
 
-    let img = if img.width() > max_width || img.height() > max_height {
-        img.resize(max_width, max_height, image::imageops::FilterType::Lanczos3)
-    } else {
-        img.clone()
-    };
+    let mut header = Vec::with_capacity(64);
+    let mut mask = Vec::new();
+    monochrome_mask(rgb_img, options.dither, &mut mask);
 
-    let (width, height) = (img.width(), img.height());
-    let rgb_img = img.to_rgb8();
-
-    let mut sixel = String::new();
-    sixel.push_str("\x1bPq");
-    sixel.push_str(&format!("\"1;1;{};{}", width, height));
+    header.extend_from_slice(b"\x1bPq");
+    header.extend_from_slice(format!("\"1;1;{};{}", width, height).as_bytes());
 
     // black and white colors
-    sixel.push_str("#0;2;0;0;0");         // Black
-    sixel.push_str("#1;2;100;100;100");   // White
-
-    for y in (0..height).step_by(6) {
-        sixel.push_str("#1");
-
-        for x in 0..width {
-            let mut sixel_char = 0u8;
-
-            for bit in 0..6 {
-                if y + bit < height {
-                    let pixel = rgb_img.get_pixel(x, y + bit);
-                    let brightness = (pixel[0] as u16 + pixel[1] as u16 + pixel[2] as u16) / 3;
+    header.extend_from_slice(b"#0;2;0;0;0");         // Black
+    header.extend_from_slice(b"#1;2;100;100;100");   // White
 
-                    if brightness > 128 {
-                        sixel_char |= 1 << bit;
-                    }
+    // bands are independent, so they are packed in parallel and joined in order
+    let width = width as usize;
+    let bands: Vec<Vec<u8>> = mask
+        .par_chunks((width * 6).max(1))
+        .map(|rows| {
+            let mut band = vec![0u8; width];
+            for (bit, row) in rows.chunks(width).enumerate() {
+                for (sixel_char, &set) in band.iter_mut().zip(row) {
+                    *sixel_char |= (set as u8) << bit;
                 }
             }
-            sixel.push((sixel_char + 63) as char);
-        }
+            band.iter_mut().for_each(|c| *c += rle::EMPTY_SIXEL);
 
-        sixel.push('$');
-        sixel.push('-');
+            let mut output = Vec::with_capacity(width / 2 + 4);
+            output.extend_from_slice(b"#1");
+            rle::push_band(&mut output, &band, options.compress);
+            output.push(b'$');
+            output.push(b'-');
+            output
+        })
+        .collect();
+    out.write_all(&header)
+        .and_then(|()| bands.iter().try_for_each(|band| out.write_all(band)))
+        .and_then(|()| out.write_all(b"\x1b\\"))
+        .map_err(|e| AurenaError::IoError { err: e })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    fn encoded_size(img: &RgbImage, compress: bool) -> usize {
+        let options = SixelOptions { compress, ..SixelOptions::default() };
+        let mut out = Vec::new();
+        monochrome_frame_convert(img, &options, &mut out).unwrap();
+        out.len()
     }
 
-    sixel.push_str("\x1b\\");
-    Ok(sixel)
-}
\ No newline at end of file
+    #[test]
+    fn flat_frames_compress() {
+        let img = RgbImage::from_pixel(320, 60, Rgb([230, 230, 230]));
+        let (compressed, plain) = (encoded_size(&img, true), encoded_size(&img, false));
+        assert!(compressed * 10 < plain, "{} bytes compressed, {} uncompressed", compressed, plain);
+    }
+}
@@ -0,0 +1,328 @@
+use crate::sixel::metric::ColorMetric;
+use crossterm::style::Color;
+use image::RgbImage;
+
+/// histogram precision used by median cut (5 bits per channel)
+const HIST_BITS: u32 = 5;
+const HIST_SIZE: usize = 1 << (HIST_BITS * 3);
+
+/// octree depth; six levels keep the tree small while leaf averages stay exact
+const OCTREE_DEPTH: usize = 6;
+
+/// build a palette of up to `max_colors` entries using median cut, splitting
+/// boxes along the widest axis of the metric's color space
+pub fn median_cut_palette(img: &RgbImage, max_colors: usize, metric: ColorMetric) -> Vec<Color> {
+    // collapse the image into a weighted histogram so large images stay cheap
+    let mut histogram = vec![0u32; HIST_SIZE];
+    for pixel in img.pixels() {
+        histogram[histogram_index(pixel.0)] += 1;
+    }
+
+    let colors: Vec<HistogramColor> = histogram
+        .iter()
+        .enumerate()
+        .filter(|&(_, &count)| count > 0)
+        .map(|(idx, &count)| {
+            let rgb = histogram_color(idx);
+            HistogramColor { rgb, coordinates: metric.coordinates(rgb), count }
+        })
+        .collect();
+
+    if colors.is_empty() {
+        return vec![Color::Rgb { r: 0, g: 0, b: 0 }];
+    }
+
+    let mut boxes = vec![ColorBox::new(colors)];
+
+    while boxes.len() < max_colors {
+        // split the box with the widest spread, weighted by how many pixels it covers
+        let Some((split_idx, _)) = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.colors.len() > 1)
+            .map(|(idx, b)| (idx, b.range().0 * b.population as f64))
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        else {
+            break;
+        };
+
+        let (low, high) = boxes.swap_remove(split_idx).split();
+        boxes.push(low);
+        boxes.push(high);
+    }
+
+    boxes.iter().map(ColorBox::average).collect()
+}
+
+/// build a palette of up to `max_colors` entries using octree reduction; the
+/// tree subdivides the metric's color space, while leaves average plain RGB
+pub fn octree_palette(img: &RgbImage, max_colors: usize, metric: ColorMetric) -> Vec<Color> {
+    let mut octree = Octree::new();
+    for pixel in img.pixels() {
+        octree.insert(metric.octree_key(pixel.0), pixel.0);
+    }
+    octree.sort_levels();
+
+    let max_colors = max_colors.max(1);
+    while octree.leaf_count > max_colors {
+        if !octree.reduce() {
+            break;
+        }
+    }
+
+    octree.palette(max_colors)
+}
+
+fn histogram_index(rgb: [u8; 3]) -> usize {
+    let shift = 8 - HIST_BITS;
+    ((rgb[0] >> shift) as usize) << (HIST_BITS * 2)
+        | ((rgb[1] >> shift) as usize) << HIST_BITS
+        | (rgb[2] >> shift) as usize
+}
+
+fn histogram_color(idx: usize) -> [u8; 3] {
+    let mask = (1 << HIST_BITS) - 1;
+    let shift = 8 - HIST_BITS;
+    // use the center of each histogram cell
+    let center = 1 << (shift - 1);
+    [
+        (((idx >> (HIST_BITS * 2)) & mask) << shift | center) as u8,
+        (((idx >> HIST_BITS) & mask) << shift | center) as u8,
+        ((idx & mask) << shift | center) as u8,
+    ]
+}
+
+/// a histogram cell with its position in the metric's color space
+struct HistogramColor {
+    rgb: [u8; 3],
+    coordinates: [f64; 3],
+    count: u32,
+}
+
+/// a box of histogram colors for median cut
+struct ColorBox {
+    colors: Vec<HistogramColor>,
+    population: u32,
+}
+
+impl ColorBox {
+    fn new(colors: Vec<HistogramColor>) -> Self {
+        let population = colors.iter().map(|color| color.count).sum();
+        ColorBox { colors, population }
+    }
+
+    /// widest axis range and the axis it belongs to
+    fn range(&self) -> (f64, usize) {
+        (0..3)
+            .map(|axis| {
+                let (min, max) = self.colors.iter().fold((f64::MAX, f64::MIN), |(min, max), color| {
+                    (min.min(color.coordinates[axis]), max.max(color.coordinates[axis]))
+                });
+                (max - min, axis)
+            })
+            .max_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)))
+            .unwrap_or((0.0, 0))
+    }
+
+    /// split at the population median along the widest axis
+    fn split(mut self) -> (ColorBox, ColorBox) {
+        let (_, axis) = self.range();
+        self.colors.sort_unstable_by(|a, b| a.coordinates[axis].total_cmp(&b.coordinates[axis]));
+
+        let half = self.population / 2;
+        let mut running = 0;
+        let mut cut = 1;
+        for (i, &HistogramColor { count, .. }) in self.colors.iter().enumerate() {
+            running += count;
+            if running >= half {
+                cut = i + 1;
+                break;
+            }
+        }
+        let cut = cut.clamp(1, self.colors.len() - 1);
+
+        let high = self.colors.split_off(cut);
+        (ColorBox::new(self.colors), ColorBox::new(high))
+    }
+
+    fn average(&self) -> Color {
+        let mut sums = [0u64; 3];
+        for color in &self.colors {
+            for (sum, value) in sums.iter_mut().zip(color.rgb) {
+                *sum += value as u64 * color.count as u64;
+            }
+        }
+        let total = (self.population as u64).max(1);
+        Color::Rgb {
+            r: (sums[0] / total) as u8,
+            g: (sums[1] / total) as u8,
+            b: (sums[2] / total) as u8,
+        }
+    }
+}
+
+#[derive(Default)]
+struct OctreeNode {
+    // child node indices, 0 meaning absent (the root is never a child)
+    children: [u32; 8],
+    sums: [u64; 3],
+    count: u64,
+    is_leaf: bool,
+}
+
+impl OctreeNode {
+    fn average(&self) -> Option<[u8; 3]> {
+        if self.count == 0 {
+            return None;
+        }
+        Some(self.sums.map(|sum| (sum / self.count) as u8))
+    }
+}
+
+/// arena-backed color octree
+struct Octree {
+    nodes: Vec<OctreeNode>,
+    // reducible (non-leaf) nodes per level
+    levels: Vec<Vec<usize>>,
+    leaf_count: usize,
+}
+
+impl Octree {
+    fn new() -> Self {
+        Octree {
+            nodes: vec![OctreeNode::default()],
+            levels: vec![Vec::new(); OCTREE_DEPTH],
+            leaf_count: 0,
+        }
+    }
+
+    /// file `rgb` under the leaf for `key`
+    fn insert(&mut self, key: [u8; 3], rgb: [u8; 3]) {
+        let mut node = 0;
+        for level in 0..OCTREE_DEPTH {
+            if self.nodes[node].is_leaf {
+                break;
+            }
+            let shift = 7 - level;
+            let child = (((key[0] >> shift) & 1) << 2 | ((key[1] >> shift) & 1) << 1 | ((key[2] >> shift) & 1)) as usize;
+
+            self.nodes[node].count += 1;
+            node = match self.nodes[node].children[child] {
+                0 => {
+                    let idx = self.nodes.len();
+                    let is_leaf = level + 1 == OCTREE_DEPTH;
+                    self.nodes.push(OctreeNode { is_leaf, ..Default::default() });
+                    if is_leaf {
+                        self.leaf_count += 1;
+                    } else {
+                        self.levels[level + 1].push(idx);
+                    }
+                    self.nodes[node].children[child] = idx as u32;
+                    idx
+                }
+                existing => existing as usize,
+            };
+        }
+
+        let leaf = &mut self.nodes[node];
+        for (sum, value) in leaf.sums.iter_mut().zip(rgb) {
+            *sum += value as u64;
+        }
+        leaf.count += 1;
+    }
+
+    /// order each level so the least populated nodes are reduced first
+    fn sort_levels(&mut self) {
+        let nodes = &self.nodes;
+        for level in self.levels.iter_mut() {
+            level.sort_unstable_by_key(|&idx| std::cmp::Reverse(nodes[idx].count));
+        }
+    }
+
+    /// fold the children of the deepest reducible node into it
+    fn reduce(&mut self) -> bool {
+        let Some(level) = (0..OCTREE_DEPTH).rev().find(|&level| !self.levels[level].is_empty()) else {
+            return false;
+        };
+        let Some(node) = self.levels[level].pop() else {
+            return false;
+        };
+
+        let children = std::mem::take(&mut self.nodes[node].children);
+        let mut sums = [0u64; 3];
+        let mut merged = 0;
+        for &child in children.iter().filter(|&&child| child != 0) {
+            let child = &self.nodes[child as usize];
+            for (sum, child_sum) in sums.iter_mut().zip(child.sums) {
+                *sum += child_sum;
+            }
+            merged += 1;
+        }
+
+        let reduced = &mut self.nodes[node];
+        reduced.sums = sums;
+        reduced.is_leaf = true;
+        self.leaf_count = self.leaf_count + 1 - merged;
+        true
+    }
+
+    /// leaf averages, at most `max_colors` of them
+    fn palette(&self, max_colors: usize) -> Vec<Color> {
+        let mut leaves = Vec::with_capacity(self.leaf_count);
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            let node = &self.nodes[node];
+            if node.is_leaf {
+                if let Some(rgb) = node.average() {
+                    leaves.push((rgb, node.count));
+                }
+            } else {
+                stack.extend(node.children.iter().filter(|&&child| child != 0).map(|&child| child as usize));
+            }
+        }
+
+        // the root is never reduced, so up to eight leaves can remain below it;
+        // past the limit, the most common colors win
+        if leaves.len() > max_colors {
+            leaves.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+            leaves.truncate(max_colors);
+        }
+
+        let mut palette: Vec<Color> = leaves.into_iter().map(|([r, g, b], _)| Color::Rgb { r, g, b }).collect();
+        if palette.is_empty() {
+            palette.push(Color::Rgb { r: 0, g: 0, b: 0 });
+        }
+        palette
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    /// one pixel in each of the eight octants of the RGB cube
+    fn octant_image() -> RgbImage {
+        RgbImage::from_fn(8, 1, |x, _| {
+            let channel = |bit: u32| if x & bit != 0 { 255 } else { 0 };
+            Rgb([channel(4), channel(2), channel(1)])
+        })
+    }
+
+    #[test]
+    fn octree_palette_stays_within_the_color_limit() {
+        let img = octant_image();
+        for max_colors in 1..=8 {
+            let palette = octree_palette(&img, max_colors, ColorMetric::Rgb);
+            assert!(palette.len() <= max_colors, "{} colors for a limit of {}", palette.len(), max_colors);
+        }
+    }
+
+    #[test]
+    fn median_cut_palette_stays_within_the_color_limit() {
+        let img = octant_image();
+        for max_colors in 1..=8 {
+            assert!(median_cut_palette(&img, max_colors, ColorMetric::Rgb).len() <= max_colors);
+        }
+    }
+}
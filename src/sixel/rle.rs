@@ -0,0 +1,74 @@
+use crate::sixel::push_fmt;
+
+/// empty sixel character (no pixels set)
+pub const EMPTY_SIXEL: u8 = b'?';
+
+/// shortest run worth replacing with a DECGRI repeat sequence
+const MIN_REPEAT: usize = 4;
+
+/// append a run of identical sixel characters, using DECGRI `!<count><char>` for long runs
+pub fn push_run(buffer: &mut Vec<u8>, sixel_char: u8, count: usize) {
+    if count >= MIN_REPEAT {
+        push_fmt(buffer, format_args!("!{}", count));
+        buffer.push(sixel_char);
+    } else {
+        buffer.extend(std::iter::repeat_n(sixel_char, count));
+    }
+}
+
+/// append one color band, run-length compressing it when enabled
+pub fn push_band(buffer: &mut Vec<u8>, band: &[u8], compress: bool) {
+    if !compress {
+        buffer.extend_from_slice(band);
+        return;
+    }
+
+    // trailing empty sixels draw nothing, so they can be dropped entirely
+    let end = band.iter().rposition(|&c| c != EMPTY_SIXEL).map_or(0, |i| i + 1);
+    let band = &band[..end];
+
+    let mut i = 0;
+    while i < band.len() {
+        let sixel_char = band[i];
+        let run = band[i..].iter().take_while(|&&c| c == sixel_char).count();
+        push_run(buffer, sixel_char, run);
+        i += run;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compressed(band: &[u8]) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        push_band(&mut buffer, band, true);
+        buffer
+    }
+
+    #[test]
+    fn short_runs_stay_literal() {
+        assert_eq!(compressed(b"~~~@@N"), b"~~~@@N");
+    }
+
+    #[test]
+    fn long_runs_become_repeats() {
+        assert_eq!(compressed(b"~~~~"), b"!4~");
+        assert_eq!(compressed(b"@~~~~~~~~~~N"), b"@!10~N");
+    }
+
+    #[test]
+    fn trailing_empty_sixels_are_dropped() {
+        assert_eq!(compressed(b"~~??????"), b"~~");
+        assert_eq!(compressed(b"??????"), b"");
+        // empty sixels before a set one still position it
+        assert_eq!(compressed(b"?????~"), b"!5?~");
+    }
+
+    #[test]
+    fn uncompressed_bands_are_copied() {
+        let mut buffer = Vec::new();
+        push_band(&mut buffer, b"~~~~~??", false);
+        assert_eq!(buffer, b"~~~~~??");
+    }
+}
@@ -1,17 +1,478 @@
-use crossterm::terminal::size;
+use crossterm::terminal::{disable_raw_mode, enable_raw_mode, size, window_size};
+use std::env;
+use std::fs::File;
+use std::io::{self, IsTerminal, Write};
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
-pub fn get_terminal_size() -> Option<(u32, u32)> {
-    match size() {
-        Ok((cols, rows)) => Some((cols as u32 * 2, rows as u32 * 6)),
-        Err(_) => None,
+/// cell size assumed when the terminal reports no pixel dimensions at all
+const DEFAULT_CELL_SIZE: (u32, u32) = (8, 16);
+
+/// cell size answered by the terminal, queried at most once per run
+static QUERIED_CELL_SIZE: OnceLock<Option<(u32, u32)>> = OnceLock::new();
+
+/// terminal size in cells together with the pixel size of one cell
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TerminalGeometry {
+    pub cols: u32,
+    pub rows: u32,
+    pub cell_width: u32,
+    pub cell_height: u32,
+}
+
+impl TerminalGeometry {
+    /// pixel area available for images; the last row stays free for the
+    /// cursor, and the height is trimmed to whole sixel bands so nothing scrolls
+    pub fn image_area(&self) -> (u32, u32) {
+        let width = self.cols * self.cell_width;
+        let height = self.rows.saturating_sub(1).max(1) * self.cell_height;
+        (width, (height / 6 * 6).max(6))
+    }
+}
+
+/// measure the terminal: TIOCGWINSZ pixel fields first, then `CSI 16 t` / `CSI 14 t`
+pub fn get_terminal_geometry() -> Option<TerminalGeometry> {
+    let (cols, rows, width_px, height_px) = match window_size() {
+        Ok(ws) => (ws.columns as u32, ws.rows as u32, ws.width as u32, ws.height as u32),
+        Err(_) => {
+            let (cols, rows) = size().ok()?;
+            (cols as u32, rows as u32, 0, 0)
+        }
+    };
+    if cols == 0 || rows == 0 {
+        return None;
+    }
+
+    let (cell_width, cell_height) = if width_px > 0 && height_px > 0 {
+        (width_px / cols, height_px / rows)
+    } else {
+        QUERIED_CELL_SIZE
+            .get_or_init(|| query_cell_size(cols, rows))
+            .unwrap_or(DEFAULT_CELL_SIZE)
+    };
+
+    Some(TerminalGeometry {
+        cols,
+        rows,
+        cell_width: cell_width.max(1),
+        cell_height: cell_height.max(1),
+    })
+}
+
+/// terminal size in character cells (columns, rows)
+pub fn get_terminal_cells() -> Option<(u32, u32)> {
+    size().ok().map(|(cols, rows)| (cols as u32, rows as u32))
+}
+
+pub fn clear_screen(out: &mut impl Write) -> io::Result<()> {
+    out.write_all(b"\x1b[2J\x1b[H")
+}
+
+pub fn cursor_home(out: &mut impl Write) -> io::Result<()> {
+    out.write_all(b"\x1b[H")
+}
+
+/// remember the cursor position (DECSC)
+pub fn save_cursor(out: &mut impl Write) -> io::Result<()> {
+    out.write_all(b"\x1b7")
+}
+
+/// return to the position remembered by `save_cursor` (DECRC)
+pub fn restore_cursor(out: &mut impl Write) -> io::Result<()> {
+    out.write_all(b"\x1b8")
+}
+
+/// hold the terminal's drawing until the frame is complete (DECSET 2026);
+/// terminals without synchronized output ignore the mode
+pub fn begin_synchronized_update(out: &mut impl Write) -> io::Result<()> {
+    out.write_all(b"\x1b[?2026h")
+}
+
+pub fn end_synchronized_update(out: &mut impl Write) -> io::Result<()> {
+    out.write_all(b"\x1b[?2026l")
+}
+
+/// switch to the alternate screen, clear it and hide the cursor
+const ENTER_PLAYBACK_SCREEN: &[u8] = b"\x1b[?1049h\x1b[?25l\x1b[2J\x1b[H";
+
+/// end any pending update, show the cursor and return to the main screen
+const LEAVE_PLAYBACK_SCREEN: &[u8] = b"\x1b[?2026l\x1b[?25h\x1b[?1049l";
+
+/// terminate a sixel or other DCS sequence cut off mid-frame (ST), and reset colors
+const RESET_OUTPUT: &[u8] = b"\x1b\\\x1b[0m";
+
+/// whether the playback screen is up and still has to be restored
+static PLAYBACK_SCREEN_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// whether a playback loop is checking for interrupts, so it can stop on its own
+static WATCHING_INTERRUPTS: AtomicBool = AtomicBool::new(false);
+
+/// set by SIGINT while a playback loop is watching
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// the alternate screen with a hidden cursor, left again when dropped
+pub struct PlaybackScreen {
+    enabled: bool,
+}
+
+impl PlaybackScreen {
+    /// take over the screen; output that is not a terminal is left alone
+    pub fn enter() -> io::Result<Self> {
+        if !io::stdout().is_terminal() {
+            return Ok(PlaybackScreen { enabled: false });
+        }
+
+        let mut stdout = io::stdout();
+        stdout.write_all(ENTER_PLAYBACK_SCREEN)?;
+        stdout.flush()?;
+        PLAYBACK_SCREEN_ACTIVE.store(true, Ordering::SeqCst);
+        Ok(PlaybackScreen { enabled: true })
+    }
+}
+
+impl Drop for PlaybackScreen {
+    fn drop(&mut self) {
+        if self.enabled && PLAYBACK_SCREEN_ACTIVE.swap(false, Ordering::SeqCst) {
+            let mut stdout = io::stdout();
+            let _ = stdout.write_all(RESET_OUTPUT);
+            let _ = stdout.write_all(LEAVE_PLAYBACK_SCREEN);
+            let _ = stdout.flush();
+        }
+    }
+}
+
+/// lets a playback loop stop cleanly on SIGINT, stopping audio and restoring
+/// the screen itself; a second interrupt ends the process right away
+pub struct InterruptWatch {
+    _private: (),
+}
+
+impl InterruptWatch {
+    pub fn start() -> Self {
+        INTERRUPTED.store(false, Ordering::SeqCst);
+        WATCHING_INTERRUPTS.store(true, Ordering::SeqCst);
+        InterruptWatch { _private: () }
+    }
+
+    pub fn interrupted(&self) -> bool {
+        INTERRUPTED.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for InterruptWatch {
+    fn drop(&mut self) {
+        WATCHING_INTERRUPTS.store(false, Ordering::SeqCst);
+    }
+}
+
+/// put the terminal back after an error: close any sequence cut off mid-write
+/// and reset colors; output that is not a terminal is left alone
+pub fn reset_terminal() {
+    if io::stdout().is_terminal() {
+        let mut stdout = io::stdout();
+        let _ = stdout.write_all(RESET_OUTPUT);
+        let _ = stdout.flush();
+    }
+}
+
+/// restore the terminal on panic or SIGINT, which would otherwise leave it inside
+/// a sixel sequence, on the alternate screen, with the cursor hidden or in raw mode
+pub fn install_restore_handlers() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        reset_terminal();
+        if PLAYBACK_SCREEN_ACTIVE.swap(false, Ordering::SeqCst) {
+            let mut stdout = io::stdout();
+            let _ = stdout.write_all(LEAVE_PLAYBACK_SCREEN);
+            let _ = stdout.flush();
+        }
+        let _ = disable_raw_mode();
+        default_hook(info);
+    }));
+    install_interrupt_handler();
+}
+
+/// terminal settings from startup, put back by the interrupt handler
+#[cfg(unix)]
+static ORIGINAL_TERMIOS: OnceLock<libc::termios> = OnceLock::new();
+
+#[cfg(unix)]
+fn install_interrupt_handler() {
+    extern "C" fn on_interrupt(signal: libc::c_int) {
+        // a watching playback loop stops on its own; only the first interrupt is left to it
+        if WATCHING_INTERRUPTS.load(Ordering::SeqCst) && !INTERRUPTED.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        let write = |bytes: &[u8]| {
+            // SAFETY: write(2) is async-signal-safe and the buffers are static
+            unsafe { libc::write(libc::STDOUT_FILENO, bytes.as_ptr().cast(), bytes.len()) };
+        };
+        write(RESET_OUTPUT);
+        if PLAYBACK_SCREEN_ACTIVE.load(Ordering::SeqCst) {
+            write(LEAVE_PLAYBACK_SCREEN);
+        }
+        // SAFETY: tcsetattr(3), signal(2) and raise(3) are async-signal-safe; re-raising
+        // with the default action ends the process the way an unhandled interrupt would
+        unsafe {
+            if let Some(termios) = ORIGINAL_TERMIOS.get() {
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, termios);
+            }
+            libc::signal(signal, libc::SIG_DFL);
+            libc::raise(signal);
+        }
+    }
+
+    if io::stdin().is_terminal() {
+        // SAFETY: termios is plain data, filled in by tcgetattr before it is read
+        let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut termios) } == 0 {
+            let _ = ORIGINAL_TERMIOS.set(termios);
+        }
+    }
+
+    // SAFETY: the handler only calls async-signal-safe functions
+    unsafe {
+        libc::signal(libc::SIGINT, on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t);
     }
 }
 
-pub fn clear_screen() {
-    print!("\x1b[2J\x1b[H");
+#[cfg(not(unix))]
+fn install_interrupt_handler() {}
+
+/// graphics support reported by the terminal
+#[derive(Debug, Clone, Default)]
+pub struct TerminalCapabilities {
+    /// DA1 attribute 4
+    pub sixel: bool,
+    /// replied OK to a kitty graphics query
+    pub kitty: bool,
+    /// identifies as a terminal speaking the iTerm2 inline image protocol
+    pub iterm: bool,
+    /// 24-bit SGR colors advertised through COLORTERM
+    pub truecolor: bool,
+    /// XTSMGRAPHICS maximum color registers
+    pub color_registers: Option<u32>,
+    /// XTSMGRAPHICS maximum sixel geometry in pixels
+    pub sixel_geometry: Option<(u32, u32)>,
 }
 
-pub fn flush_display() -> std::io::Result<()> {
-    use std::io::Write;
-    std::io::stdout().flush()
-}
\ No newline at end of file
+/// queries sent during probing; DA1 goes last because every terminal answers it
+const PROBE_QUERIES: &str = concat!(
+    "\x1b_Gi=31,s=1,v=1,a=q,t=d,f=24;AAAA\x1b\\", // kitty graphics query
+    "\x1b[>0q",                                   // XTVERSION
+    "\x1b[?1;1;0S",                               // XTSMGRAPHICS color registers
+    "\x1b[?2;1;0S",                               // XTSMGRAPHICS sixel geometry
+    "\x1b[c",                                     // primary device attributes
+);
+
+/// give up on terminals that never answer
+const PROBE_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// detect graphics support by querying the terminal in raw mode
+pub fn probe_capabilities() -> TerminalCapabilities {
+    let mut caps = TerminalCapabilities::from_env();
+
+    // nothing to ask when output is piped or input is not a terminal
+    if let Some(response) = query_in_raw_mode(PROBE_QUERIES) {
+        caps.parse_response(&response);
+    }
+    caps
+}
+
+/// ask the terminal for its cell size (`CSI 16 t`), or derive it from the
+/// window pixel size (`CSI 14 t`); DA1 terminates the exchange early
+fn query_cell_size(cols: u32, rows: u32) -> Option<(u32, u32)> {
+    let response = query_in_raw_mode("\x1b[16t\x1b[14t\x1b[c")?;
+
+    let mut window = None;
+    for (params, final_byte) in csi_replies(&response, "\x1b[") {
+        match (final_byte, params.as_slice()) {
+            // CSI 6 ; height ; width t
+            ('t', [6, height, width]) if *width > 0 && *height > 0 => return Some((*width, *height)),
+            // CSI 4 ; height ; width t
+            ('t', [4, height, width]) if *width > 0 && *height > 0 => window = Some((*width / cols, *height / rows)),
+            _ => {}
+        }
+    }
+    window
+}
+
+/// send `queries` with the terminal in raw mode, returning whatever it answered
+fn query_in_raw_mode(queries: &str) -> Option<String> {
+    // nothing to ask when output is piped
+    if !io::stdout().is_terminal() {
+        return None;
+    }
+    // with media piped into stdin the replies arrive on the controlling terminal
+    let tty = if io::stdin().is_terminal() { None } else { Some(File::open("/dev/tty").ok()?) };
+
+    enable_raw_mode().ok()?;
+    let response = query_terminal(queries, tty.as_ref(), PROBE_TIMEOUT);
+    let _ = disable_raw_mode();
+    response
+}
+
+impl TerminalCapabilities {
+    /// hints available without talking to the terminal
+    fn from_env() -> Self {
+        let colorterm = env::var("COLORTERM").unwrap_or_default();
+        let term_program = env::var("TERM_PROGRAM").unwrap_or_default();
+
+        TerminalCapabilities {
+            truecolor: colorterm == "truecolor" || colorterm == "24bit",
+            iterm: matches!(term_program.as_str(), "iTerm.app" | "WezTerm" | "mintty"),
+            ..Default::default()
+        }
+    }
+
+    fn parse_response(&mut self, response: &str) {
+        if response.contains("\x1b_Gi=31;OK") {
+            self.kitty = true;
+        }
+
+        // XTVERSION: DCS > | name version ST
+        if let Some(start) = response.find("\x1bP>|") {
+            let rest = &response[start + 4..];
+            let version = rest.split('\x1b').next().unwrap_or_default();
+            if ["iTerm2", "WezTerm", "mintty"].iter().any(|name| version.contains(name)) {
+                self.iterm = true;
+            }
+        }
+
+        for (params, final_byte) in csi_replies(response, "\x1b[?") {
+            match (final_byte, params.as_slice()) {
+                // DA1: CSI ? 62 ; 4 ; ... c
+                ('c', [_, attrs @ ..]) => self.sixel = attrs.contains(&4),
+                // XTSMGRAPHICS: CSI ? item ; status ; values S
+                ('S', [1, 0, registers, ..]) => self.color_registers = Some(*registers),
+                ('S', [2, 0, width, height, ..]) => self.sixel_geometry = Some((*width, *height)),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// write `queries` and collect replies until the DA1 answer arrives or time runs out
+#[cfg(unix)]
+fn query_terminal(queries: &str, tty: Option<&File>, timeout: Duration) -> Option<String> {
+    use std::os::fd::AsRawFd;
+
+    let fd = tty.map_or(libc::STDIN_FILENO, |tty| tty.as_raw_fd());
+    let mut stdout = io::stdout();
+    stdout.write_all(queries.as_bytes()).ok()?;
+    stdout.flush().ok()?;
+
+    let deadline = Instant::now() + timeout;
+    let mut response = Vec::new();
+    let mut buf = [0u8; 256];
+
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            break;
+        }
+
+        let mut pollfd = libc::pollfd { fd, events: libc::POLLIN, revents: 0 };
+        // SAFETY: pollfd is a valid, initialized pollfd for the duration of the call
+        let ready = unsafe { libc::poll(&mut pollfd, 1, remaining.as_millis() as libc::c_int) };
+        if ready <= 0 {
+            break;
+        }
+
+        // SAFETY: buf is valid for writes of buf.len() bytes
+        let read = unsafe { libc::read(fd, buf.as_mut_ptr().cast(), buf.len()) };
+        if read <= 0 {
+            break;
+        }
+        response.extend_from_slice(&buf[..read as usize]);
+
+        let text = String::from_utf8_lossy(&response);
+        if csi_replies(&text, "\x1b[?").any(|(_, final_byte)| final_byte == 'c') {
+            break;
+        }
+    }
+
+    Some(String::from_utf8_lossy(&response).into_owned())
+}
+
+#[cfg(not(unix))]
+fn query_terminal(_queries: &str, _tty: Option<&File>, _timeout: Duration) -> Option<String> {
+    None
+}
+
+/// iterate over replies starting with `introducer` (`CSI` or `CSI ?`) as (numeric params, final byte)
+fn csi_replies<'a>(text: &'a str, introducer: &'a str) -> impl Iterator<Item = (Vec<u32>, char)> + 'a {
+    text.split(introducer).skip(1).filter_map(|reply| {
+        let end = reply.find(|c: char| !(c.is_ascii_digit() || c == ';'))?;
+        let final_byte = reply[end..].chars().next()?;
+        let params = reply[..end]
+            .split(';')
+            .filter_map(|p| p.parse().ok())
+            .collect();
+        Some((params, final_byte))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed(response: &str) -> TerminalCapabilities {
+        let mut caps = TerminalCapabilities::default();
+        caps.parse_response(response);
+        caps
+    }
+
+    #[test]
+    fn csi_replies_split_params_and_final_byte() {
+        let replies: Vec<_> = csi_replies("\x1b[?1;0;256S\x1b[?62;4c\x1b[?1;0", "\x1b[?").collect();
+        assert_eq!(replies, vec![(vec![1, 0, 256], 'S'), (vec![62, 4], 'c')]);
+    }
+
+    #[test]
+    fn xterm_with_sixel() {
+        let caps = parsed(concat!(
+            "\x1bP>|XTerm(390)\x1b\\",
+            "\x1b[?1;0;1024S",
+            "\x1b[?2;0;1000;1000S",
+            "\x1b[?63;1;2;4;6;9;15;16;22;28c",
+        ));
+        assert!(caps.sixel);
+        assert!(!caps.kitty && !caps.iterm);
+        assert_eq!(caps.color_registers, Some(1024));
+        assert_eq!(caps.sixel_geometry, Some((1000, 1000)));
+    }
+
+    #[test]
+    fn terminal_without_sixel() {
+        // the conformance level comes first and is not an attribute
+        let caps = parsed("\x1b[?62;22c");
+        assert!(!caps.sixel);
+        assert_eq!(caps.color_registers, None);
+        assert_eq!(caps.sixel_geometry, None);
+    }
+
+    #[test]
+    fn failed_graphics_queries_are_ignored() {
+        // a non-zero status means the item is unknown or failed
+        let caps = parsed("\x1b[?1;3;0S\x1b[?2;3;0;0S\x1b[?65;1;4c");
+        assert!(caps.sixel);
+        assert_eq!(caps.color_registers, None);
+        assert_eq!(caps.sixel_geometry, None);
+    }
+
+    #[test]
+    fn kitty_graphics_reply() {
+        assert!(parsed("\x1b_Gi=31;OK\x1b\\\x1b[?62;22c").kitty);
+        assert!(!parsed("\x1b_Gi=31;ENOTSUPPORTED:no graphics\x1b\\\x1b[?62;22c").kitty);
+    }
+
+    #[test]
+    fn iterm_protocol_terminals_by_version() {
+        assert!(parsed("\x1bP>|WezTerm 20240203-110809-5046fc22\x1b\\\x1b[?65;4;6c").iterm);
+        assert!(parsed("\x1bP>|iTerm2 3.5.0\x1b\\\x1b[?62;4c").iterm);
+        assert!(!parsed("\x1bP>|foot(1.16.2)\x1b\\\x1b[?62;4;22c").iterm);
+    }
+}